@@ -0,0 +1,63 @@
+# Compares the row-major `fetch` with the columnar `fetch_columns`.
+#
+# Run from a project that has the extension installed:
+#     godot --headless -s bench/fetch_columns.gd
+#
+# Only the main thread's work is timed: the `poll(0)` call that delivers a
+# result is the one that runs `shape::build` on it, so the query itself, which
+# both paths run the same way on the worker, and the polling interval are left
+# out. The results are printed as the rows of the table in the README's
+# "Columnar Results" section.
+extends SceneTree
+
+
+const ROWS := [1_000, 10_000, 100_000]
+const RUNS := 5
+
+
+func _init() -> void:
+	var aslet := Aslet.new()
+	var db := aslet.open(":memory:").wait()[1] as AsletConn
+	db.exec("create table samples (id integer, x real, y real, label text)", []).wait()
+
+	print("| Rows | `fetch` (ms) | `fetch_columns` (ms) | Speedup |")
+	print("| ---: | ---: | ---: | ---: |")
+	var inserted := 0
+	for count in ROWS:
+		var rows := []
+		for i in range(inserted, count):
+			rows.push_back([i, randf(), randf(), "sample %d" % i])
+		db.batch_insert("insert into samples values (?1, ?2, ?3, ?4)", rows).wait()
+		inserted = count
+
+		var sql := "select * from samples limit %d" % count
+		var row_major := _measure(aslet, func(): return db.fetch(sql, []))
+		var columnar := _measure(aslet, func(): return db.fetch_columns(sql, []))
+		print("| %d | %.2f | %.2f | %.1fx |" % [
+			count, row_major, columnar, row_major / columnar,
+		])
+
+	quit()
+
+
+# Returns the best of `RUNS` times spent building the result of the task
+# returned by `start` on the main thread, in milliseconds.
+#
+# `poll(0)` handles at most one result, and returns right away if none is
+# ready yet.
+func _measure(aslet: Aslet, start: Callable) -> float:
+	var best := INF
+	for i in RUNS:
+		var task := start.call() as AsletTask
+		var results := []
+		task.done.connect(func(result): results.push_back(result))
+
+		var elapsed := 0
+		while results.is_empty():
+			var begin := Time.get_ticks_usec()
+			aslet.poll(0)
+			elapsed = Time.get_ticks_usec() - begin
+
+		assert(results[0][0] == OK)
+		best = minf(best, elapsed / 1000.0)
+	return best
@@ -1,15 +1,29 @@
-use std::time::{Duration, Instant};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 use crossbeam::channel::{Receiver, RecvTimeoutError};
 use godot::{global::printerr, prelude::*};
 
 use crate::{
-    api::{conn::AsletConn, task::AsletTask, transaction::AsletTransaction},
+    api::{
+        conn::AsletConn, cursor::AsletCursor, live_query::AsletLiveQuery,
+        savepoint::AsletSavepoint, statement::AsletStatement, task::AsletTask,
+        transaction::AsletTransaction,
+    },
+    backup::BackupWatch,
+    codec::{self, DeclaredTypes, Decoder},
     error::{Error, InternalError},
     failed, ok,
+    options::OpenOptions,
+    shape,
     tasks::{TaskContext, Tasks},
+    types::{Columns, Format, Rows},
     worker::{
         Worker,
+        changes::Changes,
         messages::{InputMessage, OutputMessage},
     },
 };
@@ -25,25 +39,269 @@ pub struct Aslet {
     base: Base<RefCounted>,
     worker: Worker,
     output_receiver: Receiver<OutputMessage>,
+    types: RefCell<DeclaredTypes>,
+    /// Backups in progress, by task id.
+    backups: RefCell<HashMap<usize, BackupWatch>>,
+    /// Writers of the tasks that create a connection, cursor, statement or transaction,
+    /// by task id.
+    writers: RefCell<HashMap<usize, Worker>>,
+    /// Connections whose changes are tracked, by writer id and connection id.
+    tracked: RefCell<HashMap<(usize, usize), InstanceId>>,
+    /// Live queries being watched, by live query id.
+    live: RefCell<HashMap<usize, InstanceId>>,
+    next_live_id: Cell<usize>,
 }
 
 #[godot_api]
 impl IRefCounted for Aslet {
     fn init(base: Base<RefCounted>) -> Self {
-        let tasks = Tasks::new();
-        let (worker, output_receiver) = Worker::new();
+        Self::with_base(base, 0)
+    }
+}
+
+#[godot_api]
+impl Aslet {
+    /// Creates an [`Aslet`] with a pool of reader threads.
+    ///
+    /// Every database opened from it is switched to WAL mode, and `fetch` calls made
+    /// directly on an [`AsletConn`] are executed by the readers, each on its own
+    /// read-only connection, while writes stay ordered on the writer thread of their
+    /// database. In-memory databases and transactions always run on the writer.
+    ///
+    /// Because reads no longer queue behind writes, a `fetch` only observes the
+    /// changes of writes that already completed, so `await` a write before reading it back.
+    ///
+    /// `Aslet.new()` is equivalent to `Aslet.with_readers(0)`.
+    ///
+    /// # Parameters
+    ///
+    /// * `readers` — Number of reader threads to spawn.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var aslet := Aslet.with_readers(4)
+    /// ```
+    #[func]
+    fn with_readers(readers: u32) -> Gd<Self> {
+        Gd::from_init_fn(|base| Self::with_base(base, readers as usize))
+    }
+
+    /// Decodes a value returned by a query back into the Godot type it was stored from.
+    ///
+    /// Parameters of types SQLite can't store directly, such as vectors, colors,
+    /// dictionaries or packed arrays, are encoded to `TEXT` or `BLOB` when bound.
+    /// This reverses that encoding. `bool`, `StringName` and `NodePath` can be
+    /// decoded from their `INTEGER` and `TEXT` forms as well.
+    ///
+    /// # Parameters
+    ///
+    /// * `value` — A value from a fetched row.
+    /// * `type` — The `Variant.Type` to decode into, e.g. `TYPE_VECTOR2`.
+    ///
+    /// # Returns
+    ///
+    /// The decoded value, or `null` if `value` isn't a valid encoding of `type`.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// await db.exec("insert into spawns (pos) values (?1)", [Vector2(3, 4)]).done
+    /// var rows := (await db.fetch("select pos from spawns", []).done)[1] as Array
+    /// var pos := Aslet.decode(rows[0][0], TYPE_VECTOR2) as Vector2
+    /// ```
+    #[func]
+    fn decode(value: Variant, r#type: VariantType) -> Variant {
+        codec::decode(&value, r#type).unwrap_or_default()
+    }
+
+    /// Registers the Godot type that values of columns declared as `decltype` decode to.
+    ///
+    /// Applies to connections with `decode_types` enabled. Declared types are matched
+    /// case-insensitively and without their size, so `vec2` also matches `VEC2(8)`.
+    /// Values are decoded as by [`decode`](Self::decode).
+    ///
+    /// # Parameters
+    ///
+    /// * `decltype` — The type name used in `CREATE TABLE`.
+    /// * `type` — The `Variant.Type` to decode into, e.g. `TYPE_VECTOR2`.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// aslet.register_type("VEC2", TYPE_VECTOR2)
+    /// aslet.register_type("COLOR", TYPE_COLOR)
+    /// await db.exec("create table units (pos VEC2, tint COLOR)", []).done
+    /// db.decode_types = true
+    /// ```
+    #[func]
+    fn register_type(&self, decltype: GString, r#type: VariantType) {
+        self.types
+            .borrow_mut()
+            .register(&decltype.to_string(), r#type);
+    }
+
+    /// Tracks the backup run by the task `task_id` until the task completes.
+    pub fn watch_backup(&self, task_id: usize, watch: BackupWatch) {
+        self.backups.borrow_mut().insert(task_id, watch);
+    }
+
+    /// Pauses or resumes the backup run by the task `task_id`, returning `false` if the
+    /// task isn't running one.
+    pub fn pause_backup(&self, task_id: usize, paused: bool) -> bool {
+        match self.backups.borrow_mut().get_mut(&task_id) {
+            Some(watch) => {
+                watch.set_paused(paused);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sends the next step of the backup run by the task `task_id` right away, even if
+    /// paused, so that the worker notices it was canceled.
+    pub fn wake_backup(&self, task_id: usize) {
+        let step = self
+            .backups
+            .borrow_mut()
+            .get_mut(&task_id)
+            .and_then(|watch| {
+                let step = watch.take_due(Instant::now(), true)?;
+                Some((watch.worker().clone(), step.0, step.1))
+            });
+        if let Some((worker, task_ctx, backup_id)) = step {
+            worker.send(InputMessage::BackupStep(task_ctx, backup_id));
+        }
+    }
+
+    /// Keeps the writer thread of `worker` running until the task `task_id` completes,
+    /// and hands it to the object the task creates.
+    pub fn hold_writer(&self, task_id: usize, worker: Worker) {
+        self.writers.borrow_mut().insert(task_id, worker);
+    }
+
+    fn take_writer(&self, task_ctx: &TaskContext) -> Worker {
+        self.writers
+            .borrow_mut()
+            .remove(&task_ctx.id())
+            .unwrap_or_else(|| self.worker.clone())
+    }
+
+    /// Sets the connection that emits the changes reported for `conn_id` by the writer
+    /// `writer_id`, or stops emitting them with `None`.
+    pub fn track_conn(&self, writer_id: usize, conn_id: usize, conn: Option<InstanceId>) {
+        let mut tracked = self.tracked.borrow_mut();
+        match conn {
+            Some(conn) => tracked.insert((writer_id, conn_id), conn),
+            None => tracked.remove(&(writer_id, conn_id)),
+        };
+    }
+
+    /// Returns the id of a new live query, which its results are sent with.
+    pub fn next_live_id(&self) -> usize {
+        let live_id = self.next_live_id.get();
+        self.next_live_id.set(live_id + 1);
+        live_id
+    }
+
+    /// Sets the live query that emits the results sent for `live_id`, or forgets it with `None`.
+    pub fn track_live(&self, live_id: usize, live: Option<InstanceId>) {
+        let mut tracked = self.live.borrow_mut();
+        match live {
+            Some(live) => tracked.insert(live_id, live),
+            None => tracked.remove(&live_id),
+        };
+    }
+
+    fn emit_live(&self, live_id: usize, result: Result<(Rows, Columns, Format), Error>) {
+        let live = self.live.borrow().get(&live_id).copied();
+        let Some(mut live) =
+            live.and_then(|id| Gd::<AsletLiveQuery>::try_from_instance_id(id).ok())
+        else {
+            return;
+        };
+
+        match result {
+            Ok((rows, columns, format)) => {
+                let rows = shape::array_rows(rows.as_ref(), &self.decoder(&columns, format));
+                live.emit_signal("changed", &[rows.to_variant(), columns.to_variant()]);
+            }
+            Err(err) => {
+                live.emit_signal(
+                    "failed",
+                    &[i64::from(&err).to_variant(), err.to_string().to_variant()],
+                );
+            }
+        }
+    }
+
+    fn decoder(&self, columns: &Columns, format: Format) -> Decoder {
+        if format.decode {
+            self.types.borrow().decoder(columns)
+        } else {
+            Decoder::none()
+        }
+    }
+
+    fn emit_changes(&self, writer_id: usize, conn_id: usize, changes: Changes) {
+        let conn = self.tracked.borrow().get(&(writer_id, conn_id)).copied();
+        // a connection freed meanwhile has nobody left to notify
+        let Some(mut conn) = conn.and_then(|id| Gd::<AsletConn>::try_from_instance_id(id).ok())
+        else {
+            return;
+        };
+
+        match changes {
+            Changes::Committed(changes) => {
+                for change in changes {
+                    conn.emit_signal(
+                        "row_changed",
+                        &[
+                            change.op.to_variant(),
+                            change.table.to_variant(),
+                            change.rowid.to_variant(),
+                        ],
+                    );
+                }
+                conn.emit_signal("committed", &[]);
+            }
+            Changes::RolledBack => {
+                conn.emit_signal("rolled_back", &[]);
+            }
+        }
+    }
+
+    fn send_backup_steps(&self) {
+        let now = Instant::now();
+        let steps: Vec<_> = self
+            .backups
+            .borrow_mut()
+            .values_mut()
+            .filter_map(|watch| {
+                let (task_ctx, backup_id) = watch.take_due(now, false)?;
+                Some((watch.worker().clone(), task_ctx, backup_id))
+            })
+            .collect();
+        for (worker, task_ctx, backup_id) in steps {
+            worker.send(InputMessage::BackupStep(task_ctx, backup_id));
+        }
+    }
+
+    fn with_base(base: Base<RefCounted>, readers: usize) -> Self {
+        let (worker, output_receiver) = Worker::new(readers);
+        let tasks = Tasks::new(worker.clone());
 
         Self {
             base,
             tasks,
             worker,
             output_receiver,
+            types: RefCell::new(DeclaredTypes::default()),
+            backups: RefCell::new(HashMap::new()),
+            writers: RefCell::new(HashMap::new()),
+            tracked: RefCell::new(HashMap::new()),
+            live: RefCell::new(HashMap::new()),
+            next_live_id: Cell::new(0),
         }
     }
-}
 
-#[godot_api]
-impl Aslet {
     /// Opens a database file at the given path.
     ///
     /// Returns an [`AsletTask`] representing the asynchronous operation.  
@@ -57,9 +315,98 @@ impl Aslet {
     /// * `path` — Path to the database file to open.
     #[func]
     fn open(&self, path: String) -> Gd<AsletTask> {
+        self.open_with(path, VarDictionary::new())
+    }
+
+    /// Opens a database file at the given path, with the given options.
+    ///
+    /// Options are applied on the worker before the connection is handed back, and are
+    /// reused for every other connection opened for the same database, such as the ones
+    /// backing transactions.
+    ///
+    /// A `res://` database that isn't on disk, as in exported projects where it is packed
+    /// into the PCK, is read through [`FileAccess`](godot::classes::FileAccess) and
+    /// loaded into memory. Open it with `read_only: true`; otherwise the in-memory copy
+    /// accepts writes, but they are lost when the connection is closed.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` — Path to the database file to open.
+    /// * `options` — A dictionary that may contain the following keys:
+    ///   - `read_only: bool` — open the database in read-only mode.
+    ///   - `create: bool` — create the database if it doesn't exist (default `true`).
+    ///   - `uri: bool` — interpret `path` as a URI filename (default `true`).
+    ///   - `shared_cache: bool` — enable SQLite's shared cache mode.
+    ///   - `busy_timeout: int` — milliseconds to wait for a locked database before failing.
+    ///   - `key: String` — the key of an encrypted database. Requires the `sqlcipher` feature.
+    ///   - `rekey: String` — a new key to encrypt the database with once opened with `key`.
+    ///   - `journal_mode`, `synchronous`, `foreign_keys`, `cache_size` — set the pragma of the same name.
+    ///   - `pragmas: Dictionary` — any other pragmas to set, applied in order.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, db]` — database opened successfully, `db` is the [`AsletConn`] instance.
+    /// * `[FAILED, code, errmsg]` — failed to open the database, or `options` contains an unknown key or an invalid value. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await aslet.open_with("user://game.db", {
+    ///     "foreign_keys": true,
+    ///     "busy_timeout": 5000,
+    ///     "pragmas": {"temp_store": "memory"},
+    /// }).done as Array
+    /// ```
+    #[func]
+    fn open_with(&self, path: String, options: VarDictionary) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create(self.to_gd());
+        match OpenOptions::try_from(options) {
+            Ok(options) => {
+                let worker = self.worker.writer(Some(&path));
+                self.hold_writer(task_ctx.id(), worker.clone());
+                worker.send(InputMessage::Open(task_ctx, path, options));
+            }
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Opens an in-memory database holding a copy of a serialized database.
+    ///
+    /// `bytes` is the content of a database file, such as the result of
+    /// [`AsletConn::serialize`]. The connection can be used like
+    /// any other, but changes are only kept in memory until serialized again.
+    ///
+    /// # Parameters
+    ///
+    /// * `bytes` — The serialized database.
+    /// * `options` — The same options as [`Aslet::open_with`]. `read_only` makes the copy
+    ///   read-only; the options that only apply to files, like `create` or `uri`, are ignored.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, db]` — database opened successfully, `db` is the [`AsletConn`] instance.
+    /// * `[FAILED, code, errmsg]` — `bytes` couldn't be loaded, or `options` contains an unknown key or an invalid value. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await aslet.open_from_bytes(save_data["sandbox"], {}).done as Array
+    /// var sandbox := result[1] as AsletConn
+    /// ```
+    #[func]
+    fn open_from_bytes(&self, bytes: PackedByteArray, options: VarDictionary) -> Gd<AsletTask> {
         let (task_ctx, task) = self.tasks.create(self.to_gd());
-        self.worker
-            .send(InputMessage::Open(task_ctx, path.to_string()));
+        match OpenOptions::try_from(options) {
+            Ok(options) => {
+                let worker = self.worker.writer(None);
+                self.hold_writer(task_ctx.id(), worker.clone());
+                worker.send(InputMessage::OpenBytes(task_ctx, bytes.to_vec(), options));
+            }
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
         task
     }
 
@@ -67,6 +414,7 @@ impl Aslet {
     ///
     /// Waits up to `timeout_ms` milliseconds for any pending task to complete.
     /// If a task completes during this time, its `done` signal will be emitted with the result.
+    /// Backups and restores only advance to their next step while polling.
     ///
     /// # Parameters
     ///
@@ -75,6 +423,7 @@ impl Aslet {
     pub fn poll(&self, timeout_ms: u64) {
         let deadline = Instant::now() + Duration::from_millis(timeout_ms);
         loop {
+            self.send_backup_steps();
             let now = Instant::now();
             let remaining = deadline.saturating_duration_since(now);
 
@@ -93,12 +442,12 @@ impl Aslet {
     fn handle_msg(&self, msg: OutputMessage) {
         match msg {
             OutputMessage::Open(task_ctx, result) => match result {
-                Ok((conn, path)) => {
+                Ok((conn, readers)) => {
                     let aslet_conn = AsletConn::new(
                         self.to_gd(),
                         conn,
-                        path,
-                        self.worker.clone(),
+                        readers,
+                        self.take_writer(&task_ctx),
                         self.tasks.clone(),
                     );
                     self.complete_task(task_ctx, ok!(aslet_conn));
@@ -106,41 +455,158 @@ impl Aslet {
                 Err(err) => self.complete_task(task_ctx, failed!(err)),
             },
             OutputMessage::Exec(task_ctx, result) => {
-                self.complete_task(task_ctx, result.map_or_else(|e| failed!(e), |v| ok!(v)));
+                self.complete_task(
+                    task_ctx,
+                    result.map_or_else(|e| failed!(e), |(affected, rowid)| ok!(affected, rowid)),
+                );
+            }
+            OutputMessage::BatchInserted(task_ctx, result) => {
+                let result = match result {
+                    Ok((affected, Some(rowids))) => {
+                        ok!(affected, rowids.into_iter().collect::<PackedInt64Array>())
+                    }
+                    Ok((affected, None)) => ok!(affected),
+                    Err(err) => failed!(err),
+                };
+                self.complete_task(task_ctx, result);
+            }
+            OutputMessage::Script(task_ctx, result) => {
+                let result = match result {
+                    Ok(()) => ok!(),
+                    Err(err) => {
+                        let mut result = failed!(err);
+                        if let Error::Script(index, offset, _) = err {
+                            result.push(&(index as i64).to_variant());
+                            result.push(&offset.to_variant());
+                        }
+                        result
+                    }
+                };
+                self.complete_task(task_ctx, result);
             }
+            OutputMessage::Migrated(task_ctx, result) => {
+                self.complete_task(
+                    task_ctx,
+                    result.map_or_else(
+                        |e| failed!(e),
+                        |versions| ok!(PackedInt64Array::from(versions.as_slice())),
+                    ),
+                );
+            }
+            OutputMessage::Serialized(task_ctx, result) => {
+                self.complete_task(
+                    task_ctx,
+                    result.map_or_else(
+                        |e| failed!(e),
+                        |bytes| ok!(PackedByteArray::from(bytes.as_slice())),
+                    ),
+                );
+            }
+            OutputMessage::Changes(writer_id, conn_id, changes) => {
+                self.emit_changes(writer_id, conn_id, changes)
+            }
+            OutputMessage::Rekeyed(task_ctx, result)
+            | OutputMessage::ChangesTracked(task_ctx, result) => {
+                self.complete_task(task_ctx, result.map_or_else(|e| failed!(e), |_| ok!()));
+            }
+            OutputMessage::LiveChanged(live_id, result) => self.emit_live(live_id, result),
             OutputMessage::Fetch(task_ctx, result) => match result {
-                Ok((rows, columns)) => {
-                    self.complete_task(task_ctx, ok!(rows, columns));
+                Ok((rows, columns, format)) => {
+                    let decoder = self.decoder(&columns, format);
+                    let result = shape::build(rows, columns, format.shape, decoder);
+                    self.complete_task(task_ctx, result.unwrap_or_else(|e| failed!(e)));
                 }
                 Err(err) => {
                     self.complete_task(task_ctx, failed!(err));
                 }
             },
+            OutputMessage::CursorOpened(task_ctx, result) => match result {
+                Ok((conn, cursor_id, columns, timeout_ms, format)) => {
+                    let cursor = AsletCursor::new(
+                        self.to_gd(),
+                        conn,
+                        cursor_id,
+                        columns.to_godot(),
+                        timeout_ms,
+                        format,
+                        self.take_writer(&task_ctx),
+                        self.tasks.clone(),
+                    );
+                    self.complete_task(task_ctx, ok!(cursor));
+                }
+                Err(err) => self.complete_task(task_ctx, failed!(err)),
+            },
+            OutputMessage::Prepared(task_ctx, result) => match result {
+                Ok((conn, stmt_id, columns, parameter_count, timeout_ms, format)) => {
+                    let statement = AsletStatement::new(
+                        self.to_gd(),
+                        conn,
+                        stmt_id,
+                        columns.to_godot(),
+                        parameter_count,
+                        timeout_ms,
+                        format,
+                        self.take_writer(&task_ctx),
+                        self.tasks.clone(),
+                    );
+                    self.complete_task(task_ctx, ok!(statement));
+                }
+                Err(err) => self.complete_task(task_ctx, failed!(err)),
+            },
             OutputMessage::TransactionStarted(task_ctx, result) => match result {
-                Ok(conn) => {
+                Ok((conn, timeout_ms, format)) => {
                     let transaction = AsletTransaction::new(
                         self.to_gd(),
                         conn,
-                        self.worker.clone(),
+                        timeout_ms,
+                        format,
+                        self.take_writer(&task_ctx),
                         self.tasks.clone(),
                     );
                     self.complete_task(task_ctx, ok!(transaction));
                 }
                 Err(err) => self.complete_task(task_ctx, failed!(err)),
             },
+            OutputMessage::SavepointStarted(task_ctx, result) => match result {
+                Ok((conn, name, state, timeout_ms, format)) => {
+                    let savepoint = AsletSavepoint::new(
+                        self.to_gd(),
+                        conn,
+                        name,
+                        state,
+                        timeout_ms,
+                        format,
+                        self.take_writer(&task_ctx),
+                        self.tasks.clone(),
+                    );
+                    self.complete_task(task_ctx, ok!(savepoint));
+                }
+                Err(err) => self.complete_task(task_ctx, failed!(err)),
+            },
             OutputMessage::TransactionRolledBack(task_ctx, result)
-            | OutputMessage::TransactionCommitted(task_ctx, result) => {
+            | OutputMessage::TransactionCommitted(task_ctx, result)
+            | OutputMessage::SavepointReleased(task_ctx, result)
+            | OutputMessage::SavepointRolledBack(task_ctx, result) => {
                 self.complete_task(task_ctx, result.map_or_else(|e| failed!(e), |v| ok!(v)));
             }
             OutputMessage::Backup(task_ctx, result) => match result {
-                Ok(backup_state) => {
-                    let main = backup_state.main();
-                    main.tick();
-                    if main.is_done() {
+                Ok(progress) => {
+                    // cloned so the callable can start other backups without a double borrow
+                    let callable = self
+                        .backups
+                        .borrow()
+                        .get(&task_ctx.id())
+                        .map(|watch| watch.progress().clone());
+                    if let Some(callable) = callable {
+                        callable.call(&[
+                            progress.pagecount.to_variant(),
+                            progress.remaining.to_variant(),
+                        ]);
+                    }
+                    if progress.done {
                         self.complete_task(task_ctx, ok!());
-                    } else {
-                        self.worker
-                            .send(InputMessage::BackupStep(task_ctx, backup_state));
+                    } else if let Some(watch) = self.backups.borrow_mut().get_mut(&task_ctx.id()) {
+                        watch.schedule(task_ctx, progress.backup_id);
                     }
                 }
                 Err(e) => {
@@ -151,12 +617,15 @@ impl Aslet {
                 task_ctx,
                 failed!(Error::Internal(InternalError::TaskCanceled)),
             ),
+            OutputMessage::Failed(task_ctx, err) => self.complete_task(task_ctx, failed!(err)),
         }
     }
 
     #[inline]
     fn complete_task(&self, task_ctx: TaskContext, result: Array<Variant>) {
         let task_id = task_ctx.id();
+        self.backups.borrow_mut().remove(&task_id);
+        self.writers.borrow_mut().remove(&task_id);
         let task = self.tasks.take(task_id);
 
         if let Some(mut task) = task {
@@ -1,10 +1,26 @@
-use godot::prelude::*;
+use std::{sync::Arc, time::Duration};
+
+use godot::{classes::FileAccess, prelude::*};
 
 use crate::{
-    api::{aslet::Aslet, task::AsletTask},
-    backup::BackupRequest,
-    tasks::Tasks,
-    worker::{Worker, messages::InputMessage},
+    api::{
+        aslet::Aslet,
+        live_query::AsletLiveQuery,
+        task::AsletTask,
+        transaction::{AsletTransaction, TransactionMode},
+    },
+    backup::{BackupRequest, BackupWatch},
+    error::InternalError,
+    migration::Migration,
+    shape::Shape,
+    tasks::{Tasks, timeout},
+    types::{Format, Params},
+    worker::{
+        ReaderSet, Schema, Worker,
+        changes::ChangeTracking,
+        live::WatchRequest,
+        messages::{InputMessage, ReadMessage},
+    },
 };
 
 /// Represents a connection to a SQLite database.
@@ -13,36 +29,118 @@ use crate::{
 pub struct AsletConn {
     aslet: Gd<Aslet>,
     conn_id: usize,
-    path: String,
+    readers: Option<Arc<ReaderSet>>,
     worker: Worker,
     tasks: Tasks,
+    /// Timeout in milliseconds applied to tasks that don't specify their own.
+    ///
+    /// Transactions started from this connection inherit the value it had when they began.
+    /// `0` (the default) means tasks never time out.
+    #[var]
+    default_timeout_ms: i64,
+    /// Whether fetched values are decoded according to the declared type of their column.
+    ///
+    /// When enabled, `BOOLEAN` columns are returned as `bool`, `JSON` columns as a
+    /// `Dictionary` or `Array`, and text in `DATETIME`, `TIMESTAMP` or `DATE` columns as a
+    /// datetime dictionary like the ones `Time` returns, while integer unix times are left
+    /// as they are. Other declared types can be registered with [`Aslet::register_type`].
+    /// Transactions, savepoints, statements and cursors inherit the value this property had
+    /// when they were created. Disabled by default.
+    #[var]
+    decode_types: bool,
+    base: Base<RefCounted>,
 }
 
 #[godot_api]
 impl AsletConn {
+    /// `op` of a row inserted, as reported by [`row_changed`](Self::row_changed).
+    #[constant]
+    pub const INSERT: i64 = 0;
+
+    /// `op` of a row updated.
+    #[constant]
+    pub const UPDATE: i64 = 1;
+
+    /// `op` of a row deleted.
+    #[constant]
+    pub const DELETE: i64 = 2;
+
+    /// Changes are not tracked. This is the default.
+    #[constant]
+    pub const CHANGES_OFF: i64 = 0;
+
+    /// Every changed row is reported, once per transaction and operation.
+    #[constant]
+    pub const CHANGES_ROWS: i64 = 1;
+
+    /// Changes are coalesced by table: each table changed by a transaction is reported
+    /// once per operation, with a `rowid` of `-1`, however many rows were changed.
+    #[constant]
+    pub const CHANGES_TABLES: i64 = 2;
+
+    /// Emitted for each row inserted, updated or deleted by a committed transaction, once
+    /// changes are tracked with [`track_changes`](Self::track_changes).
+    ///
+    /// `op` is one of `INSERT`, `UPDATE` or `DELETE`.
+    #[signal]
+    fn row_changed(op: i64, table: GString, rowid: i64);
+
+    /// Emitted after the `row_changed` signals of a transaction that was committed.
+    #[signal]
+    fn committed();
+
+    /// Emitted when a transaction is rolled back, discarding its changes.
+    #[signal]
+    fn rolled_back();
+
     pub fn new(
         aslet: Gd<Aslet>,
         conn_id: usize,
-        path: String,
+        readers: Option<Arc<ReaderSet>>,
         worker: Worker,
         tasks: Tasks,
     ) -> Gd<Self> {
-        Gd::from_object(Self {
+        Gd::from_init_fn(|base| Self {
             aslet,
             conn_id,
-            path,
+            readers,
             worker,
             tasks,
+            default_timeout_ms: 0,
+            decode_types: false,
+            base,
         })
     }
 
+    fn format(&self) -> Format {
+        Format {
+            decode: self.decode_types,
+            ..Format::default()
+        }
+    }
+
     /// Starts a new database transaction.
     ///
     /// This function requests the worker to begin a new transaction, returning an [`AsletTask`]
     /// that yields the resulting [`AsletTransaction`] object once it’s ready.
     ///
-    /// The transaction uses its own dedicated connection, ensuring isolation from concurrent
-    /// asynchronous operations on other connections.
+    /// By default the transaction uses its own dedicated connection, opened with the same
+    /// options as this one, ensuring isolation from concurrent asynchronous operations on
+    /// other connections.
+    ///
+    /// With `same_connection`, the transaction runs on this connection's own handle instead.
+    /// Any other work sent to this connection, including from other transactions, then waits
+    /// until the transaction is committed or rolled back, so don't `await` it before doing so.
+    /// The exception is a `fetch` run by the reader threads of [`Aslet::with_readers`], which
+    /// runs right away and doesn't see the changes made by the transaction until it commits.
+    /// Transactions on in-memory databases always run on the connection's own handle, since a
+    /// dedicated connection would see a different, empty database.
+    ///
+    /// # Parameters
+    ///
+    /// * `mode` — One of `AsletTransaction.DEFERRED`, `AsletTransaction.IMMEDIATE` or
+    ///   `AsletTransaction.EXCLUSIVE`, controlling when the transaction acquires its locks.
+    /// * `same_connection` — Run the transaction on this connection instead of a dedicated one.
     ///
     /// # Returns
     ///
@@ -53,7 +151,7 @@ impl AsletConn {
     ///
     /// # Example
     /// ```gdscript
-    /// var result := await db.transaction().done as Array
+    /// var result := await db.transaction(AsletTransaction.IMMEDIATE).done as Array
     /// if result[0] == OK:
     ///     var tx := result[1] as AsletTransaction
     ///     await tx.exec("insert into test values (?1)", [42]).done
@@ -62,12 +160,28 @@ impl AsletConn {
     ///     push_error(result[1])
     /// ```
     #[func]
-    fn transaction(&self) -> Gd<AsletTask> {
+    fn transaction(
+        &self,
+        #[opt(default = AsletTransaction::DEFERRED)] mode: i64,
+        #[opt(default = false)] same_connection: bool,
+    ) -> Gd<AsletTask> {
         let (task_ctx, task) = self.tasks.create(self.aslet.clone());
-        self.worker.send(InputMessage::BeginTransaction(
-            task_ctx,
-            self.path.to_string(),
-        ));
+        match TransactionMode::try_from(mode) {
+            Ok(mode) => {
+                self.aslet
+                    .bind()
+                    .hold_writer(task_ctx.id(), self.worker.clone());
+                self.worker.send(InputMessage::BeginTransaction(
+                    task_ctx,
+                    self.conn_id,
+                    mode,
+                    same_connection,
+                    self.default_timeout_ms,
+                    self.format(),
+                ))
+            }
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
         task
     }
 
@@ -79,13 +193,20 @@ impl AsletConn {
     /// # Parameters
     ///
     /// * `sql` — The SQL insert statement, with placeholders (`?1`, `?2`, etc.) for parameters.
-    /// * `rows` — An array of arrays, where each inner array represents the parameter values for one row.
+    /// * `rows` — An array where each entry holds the parameters for one row, either as an array
+    ///   of values or as a [`VarDictionary`] keyed by parameter name.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take, including the time spent
+    ///   waiting in the queue. Once it elapses the statement is interrupted and the task fails
+    ///   with the `Timeout` error code. Negative values use `default_timeout_ms`, and `0` disables it.
+    /// * `return_rowids` — When `true`, the result also carries the rowid generated by each row.
     ///
     /// # Returns
     ///
     /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
     ///
-    /// * `[OK]` — batch insert done successfully.
+    /// * `[OK, n]` — batch insert done successfully, where `n` is the number of affected rows.
+    /// * `[OK, n, rowids]` — same as above when `return_rowids` is set. `rowids` is a
+    ///   [`PackedInt64Array`] with one entry per row, holding `0` for rows that inserted nothing.
     /// * `[FAILED, code, errmsg]` — insert failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
     ///
     /// # Example
@@ -98,14 +219,27 @@ impl AsletConn {
     ///     push_error(result[1])
     /// ```
     #[func]
-    fn batch_insert(&self, sql: GString, rows: Array<Array<Variant>>) -> Gd<AsletTask> {
-        let (task_ctx, task) = self.tasks.create(self.aslet.clone());
-        self.worker.send(InputMessage::BatchInsert(
-            self.conn_id,
-            task_ctx,
-            sql.into(),
-            rows.into(),
-        ));
+    fn batch_insert(
+        &self,
+        sql: GString,
+        rows: Array<Variant>,
+        #[opt(default = -1)] timeout_ms: i64,
+        #[opt(default = false)] return_rowids: bool,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Params::batch(rows) {
+            Ok(rows) => self.worker.send(InputMessage::BatchInsert(
+                self.conn_id,
+                task_ctx,
+                sql.into(),
+                rows,
+                return_rowids,
+            )),
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
         task
     }
 
@@ -117,13 +251,18 @@ impl AsletConn {
     /// # Parameters
     ///
     /// * `sql` — The SQL statement to execute.
-    /// * `params` — Statement parameters to bind, as an array of [`Variant`] values.
+    /// * `params` — Statement parameters to bind, as an array of [`Variant`] values or a
+    ///   [`VarDictionary`] keyed by parameter name (`:name`, `@name` or `$name`, prefix optional).
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take, including the time spent
+    ///   waiting in the queue. Once it elapses the statement is interrupted and the task fails
+    ///   with the `Timeout` error code. Negative values use `default_timeout_ms`, and `0` disables it.
     ///
     /// # Returns
     ///
     /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
     ///
-    /// * `[OK, n]` — statement executed successfully, where `n` is the number of affected rows.
+    /// * `[OK, n, rowid]` — statement executed successfully, where `n` is the number of affected rows
+    ///   and `rowid` is the rowid of the last row inserted through the connection.
     /// * `[FAILED, code, errmsg]` — execution failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
     ///
     /// # Example
@@ -135,26 +274,235 @@ impl AsletConn {
     ///     push_error(result[1])
     /// ```
     #[func]
-    fn exec(&self, sql: GString, params: Array<Variant>) -> Gd<AsletTask> {
-        let (task_ctx, task) = self.tasks.create(self.aslet.clone());
-        self.worker.send(InputMessage::Exec(
+    fn exec(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Params::try_from(params) {
+            Ok(params) => self.worker.send(InputMessage::Exec(
+                self.conn_id,
+                task_ctx,
+                sql.into(),
+                params,
+            )),
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Executes a SQL script made of several statements, such as a schema file.
+    ///
+    /// The statements run one after the other without parameters, and any rows they return
+    /// are discarded. Execution stops at the first statement that fails.
+    ///
+    /// # Parameters
+    ///
+    /// * `sql` — The SQL statements to execute, separated by `;`.
+    /// * `transaction` — When `true`, the script runs inside an implicit transaction, so that a
+    ///   failure rolls back every statement executed before it. Ignored when the connection is
+    ///   already inside a transaction, in which case the script becomes part of it.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take, including the time spent
+    ///   waiting in the queue. Once it elapses the statement is interrupted and the task fails
+    ///   with the `Timeout` error code. Negative values use `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK]` — script executed successfully.
+    /// * `[FAILED, code, errmsg, index, offset]` — a statement failed. `index` is the position of
+    ///   that statement in the script, starting at `0`, and `offset` is the byte offset of the
+    ///   token SQLite rejected, or `-1` when the statement failed while running.
+    /// * `[FAILED, code, errmsg]` — the script could not be started.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.exec_script("""
+    ///     create table players (id integer primary key, name text);
+    ///     create index players_name on players (name);
+    /// """, true).done as Array
+    /// if result[0] == FAILED:
+    ///     push_error(result[2])
+    /// ```
+    #[func]
+    fn exec_script(
+        &self,
+        sql: GString,
+        #[opt(default = false)] transaction: bool,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        self.worker.send(InputMessage::ExecScript(
             self.conn_id,
             task_ctx,
             sql.into(),
-            params.into(),
+            transaction,
         ));
         task
     }
 
+    /// Executes the SQL script stored in a file.
+    ///
+    /// The file is read through [`FileAccess`], so `res://` and `user://` paths are supported,
+    /// including files packed in an exported project. See [`AsletConn::exec_script`] for how the
+    /// script runs and the result of the task.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` — Path to the script file.
+    /// * `transaction` — When `true`, the script runs inside an implicit transaction.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.exec_file("res://schema.sql", true).done as Array
+    /// if result[0] == FAILED:
+    ///     push_error(result[2])
+    /// ```
+    #[func]
+    fn exec_file(
+        &self,
+        path: GString,
+        #[opt(default = false)] transaction: bool,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        let sql = FileAccess::get_file_as_string(&path);
+        match FileAccess::get_open_error() {
+            godot::global::Error::OK => self.worker.send(InputMessage::ExecScript(
+                self.conn_id,
+                task_ctx,
+                sql.into(),
+                transaction,
+            )),
+            err => self.worker.reject(
+                task_ctx,
+                InternalError::FileNotReadable(path.into(), err).into(),
+            ),
+        }
+        task
+    }
+
+    /// Brings the database schema up to date by applying the migrations it is missing.
+    ///
+    /// Each migration is a SQL script with a version, and the scripts in `migrations` are
+    /// numbered from `1` in array order, so new migrations must always be appended. Every
+    /// migration not applied yet runs in version order inside a single transaction, so
+    /// either all of them are applied or none is.
+    ///
+    /// Applied migrations are recorded in the `aslet_migrations` table together with a
+    /// checksum of their script, and `PRAGMA user_version` is set to the highest version
+    /// applied. The task fails without applying anything if a migration that was already
+    /// applied has been edited since. Recorded versions missing from `migrations` are ignored.
+    ///
+    /// # Parameters
+    ///
+    /// * `migrations` — An array of SQL scripts, one per migration.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take, including the time spent
+    ///   waiting in the queue. Negative values use `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, versions]` — database up to date, `versions` is a [`PackedInt64Array`] with the
+    ///   versions applied by this call, empty if there was nothing to apply.
+    /// * `[FAILED, code, errmsg]` — migration failed and every change was rolled back. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.migrate([
+    ///     "create table players (id integer primary key, name text)",
+    ///     "alter table players add column score integer not null default 0",
+    /// ]).done as Array
+    /// if result[0] == FAILED:
+    ///     push_error(result[2])
+    /// ```
+    #[func]
+    fn migrate(
+        &self,
+        migrations: Array<Variant>,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Migration::from_array(migrations) {
+            Ok(migrations) => {
+                self.worker
+                    .send(InputMessage::Migrate(self.conn_id, task_ctx, migrations))
+            }
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Applies the migrations stored as `.sql` files in a directory.
+    ///
+    /// The version of each migration is taken from the digits its file name starts with,
+    /// so `0002_add_scores.sql` is version `2`, and files are applied in version order.
+    /// Files are read through [`DirAccess`] and [`FileAccess`], so `res://` directories work,
+    /// as long as `*.sql` is included in the non-resource files of the export preset.
+    /// See [`AsletConn::migrate`] for how migrations are tracked and the result of the task.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` — Path to the directory holding the migration files.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.migrate_dir("res://migrations").done as Array
+    /// if result[0] == OK:
+    ///     print("Applied migrations: ", result[1])
+    /// ```
+    #[func]
+    fn migrate_dir(&self, path: GString, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Migration::from_dir(&path) {
+            Ok(migrations) => {
+                self.worker
+                    .send(InputMessage::Migrate(self.conn_id, task_ctx, migrations))
+            }
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
     /// Executes a SQL query and retrieves rows.
     ///
     /// This function sends the query and its parameters to the worker thread for execution.
     /// It returns an [`AsletTask`] representing the asynchronous operation.
     ///
+    /// When the [`Aslet`] was created with [`Aslet::with_readers`], the query runs on one of
+    /// the reader threads and only sees writes that have already completed.
+    ///
     /// # Parameters
     ///
     /// * `sql` — The SQL query to execute.
-    /// * `params` — Query parameters to bind, as an array of [`Variant`] values.
+    /// * `params` — Query parameters to bind, as an array of [`Variant`] values or a
+    ///   [`VarDictionary`] keyed by parameter name (`:name`, `@name` or `$name`, prefix optional).
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take, including the time spent
+    ///   waiting in the queue. Once it elapses the statement is interrupted and the task fails
+    ///   with the `Timeout` error code. Negative values use `default_timeout_ms`, and `0` disables it.
     ///
     /// # Returns
     ///
@@ -173,26 +521,461 @@ impl AsletConn {
     ///     push_error(result[1])
     /// ```
     #[func]
-    fn fetch(&self, sql: GString, params: Array<Variant>) -> Gd<AsletTask> {
-        let (task_ctx, task) = self.tasks.create(self.aslet.clone());
-        self.worker.send(InputMessage::Fetch(
+    fn fetch(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Rows)
+    }
+
+    /// Executes a SQL query and retrieves rows as dictionaries keyed by column name.
+    ///
+    /// Takes the same parameters as [`fetch`](Self::fetch). If several columns share a
+    /// name, the value of the last one is kept.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, rows]` — query executed successfully, with `rows` as an `Array[Dictionary]`.
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.fetch_dicts("select id, name from users", []).done as Array
+    /// for user in result[1]:
+    ///     print(user.id, user.name)
+    /// ```
+    #[func]
+    fn fetch_dicts(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Dicts)
+    }
+
+    /// Executes a SQL query that must return exactly one row.
+    ///
+    /// Takes the same parameters as [`fetch`](Self::fetch).
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, row]` — query returned one row, as a `Dictionary` keyed by column name.
+    /// * `[FAILED, code, errmsg]` — query failed, or returned no rows (`QueryReturnedNoRows`)
+    ///   or more than one (`QueryReturnedMoreThanOneRow`).
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.fetch_one("select * from players where id = :id", {"id": id}).done as Array
+    /// if result[0] == OK:
+    ///     print(result[1].name)
+    /// ```
+    #[func]
+    fn fetch_one(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::One)
+    }
+
+    /// Executes a SQL query that returns at most one row.
+    ///
+    /// Takes the same parameters as [`fetch`](Self::fetch).
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, row]` — query executed successfully, `row` being a `Dictionary` keyed by column
+    ///   name, or `null` if no row was returned.
+    /// * `[FAILED, code, errmsg]` — query failed, or returned more than one row (`QueryReturnedMoreThanOneRow`).
+    #[func]
+    fn fetch_optional(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Optional)
+    }
+
+    /// Executes a SQL query and retrieves the first column of its first row.
+    ///
+    /// Takes the same parameters as [`fetch`](Self::fetch).
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, value]` — query executed successfully.
+    /// * `[FAILED, code, errmsg]` — query failed, or returned no rows (`QueryReturnedNoRows`).
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var count := (await db.fetch_value("select count(*) from users", []).done)[1] as int
+    /// ```
+    #[func]
+    fn fetch_value(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Value)
+    }
+
+    /// Executes a SQL query and retrieves every value of one of its columns.
+    ///
+    /// Values are collected into the narrowest container that holds all of them: a
+    /// `PackedInt64Array` for integers, a `PackedFloat64Array` for numbers, a
+    /// `PackedStringArray` for text, and an `Array` otherwise, including for blobs,
+    /// columns containing `NULL`, and columns decoded by their declared type.
+    ///
+    /// # Parameters
+    ///
+    /// * `sql`, `params` — As in [`fetch`](Self::fetch).
+    /// * `column` — Index of the column to collect.
+    /// * `timeout_ms` — As in [`fetch`](Self::fetch).
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, values]` — query executed successfully.
+    /// * `[FAILED, code, errmsg]` — query failed, or `column` is out of range (`InvalidColumnIndex`).
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var ids := (await db.fetch_column("select id from users", []).done)[1] as PackedInt64Array
+    /// ```
+    #[func]
+    fn fetch_column(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = 0)] column: u32,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Column(column as usize))
+    }
+
+    /// Executes a SQL query and retrieves its result column by column.
+    ///
+    /// Building a packed array per column is much cheaper for the main thread than
+    /// building an array per row, which makes this the fastest way to read large results.
+    /// Each column is collected as in [`fetch_column`](Self::fetch_column): into a
+    /// `PackedInt64Array`, `PackedFloat64Array` or `PackedStringArray` when all of its
+    /// values fit, and into an `Array` otherwise.
+    ///
+    /// Takes the same parameters as [`fetch`](Self::fetch).
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, columns]` — query executed successfully, with `columns` as a `Dictionary` of
+    ///   column name to the values of that column, in query order.
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.fetch_columns("select x, y from samples", []).done as Array
+    /// var xs := result[1].x as PackedFloat64Array
+    /// var ys := result[1].y as PackedFloat64Array
+    /// ```
+    #[func]
+    fn fetch_columns(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Columns)
+    }
+
+    fn fetch_shaped(
+        &self,
+        sql: GString,
+        params: Variant,
+        timeout_ms: i64,
+        shape: Shape,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        let params = match Params::try_from(params) {
+            Ok(params) => params,
+            Err(err) => {
+                self.worker.reject(task_ctx, err.into());
+                return task;
+            }
+        };
+        match self.readers.as_ref() {
+            Some(readers) => self.worker.send_read(ReadMessage::Fetch(
+                readers.clone(),
+                task_ctx,
+                sql.into(),
+                params,
+                self.format().with_shape(shape),
+            )),
+            None => self.worker.send(InputMessage::Fetch(
+                self.conn_id,
+                task_ctx,
+                sql.into(),
+                params,
+                self.format().with_shape(shape),
+            )),
+        }
+        task
+    }
+
+    /// Executes a SQL query whose rows are read in batches.
+    ///
+    /// Unlike [`fetch`](Self::fetch), rows are not collected up front: the statement stays
+    /// prepared on the worker and each call to [`AsletCursor::next_batch`] steps it further.
+    /// Cursors always run on the writer thread.
+    ///
+    /// # Parameters
+    ///
+    /// * `sql` — The SQL query to execute.
+    /// * `params` — Query parameters to bind, as an array of [`Variant`] values or a
+    ///   [`VarDictionary`] keyed by parameter name (`:name`, `@name` or `$name`, prefix optional).
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it. Batches read from the cursor use
+    ///   `default_timeout_ms` unless they specify their own.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, cursor]` — query started successfully, `cursor` is the [`AsletCursor`] instance.
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    #[func]
+    fn query(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Params::try_from(params) {
+            Ok(params) => {
+                self.aslet
+                    .bind()
+                    .hold_writer(task_ctx.id(), self.worker.clone());
+                self.worker.send(InputMessage::OpenCursor(
+                    self.conn_id,
+                    task_ctx,
+                    sql.into(),
+                    params,
+                    self.default_timeout_ms,
+                    self.format(),
+                ))
+            }
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Lists the tables of the database, leaving out SQLite's internal tables.
+    ///
+    /// # Parameters
+    ///
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, tables]` — `tables` is an array with one [`VarDictionary`] per table, sorted by
+    ///   name, with the keys `name`, `column_count`, `without_rowid` (`bool`), `strict` (`bool`)
+    ///   and `sql`, the statement that created the table.
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    #[func]
+    fn tables(&self, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        self.schema(Schema::Tables, timeout_ms)
+    }
+
+    /// Lists the views of the database.
+    ///
+    /// # Parameters
+    ///
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, views]` — `views` is an array with one [`VarDictionary`] per view, sorted by
+    ///   name, with the keys `name` and `sql`, the statement that created the view.
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    #[func]
+    fn views(&self, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        self.schema(Schema::Views, timeout_ms)
+    }
+
+    /// Describes the columns of a table or view, in declaration order.
+    ///
+    /// # Parameters
+    ///
+    /// * `table` — Name of the table or view.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, columns]` — `columns` is an array with one [`VarDictionary`] per column, empty if
+    ///   there is no table called `table`. Its keys are:
+    ///   - `name` — name of the column.
+    ///   - `type` — declared type, or an empty string if the column has none.
+    ///   - `nullable` — `false` when the column is `NOT NULL`.
+    ///   - `default` — SQL text of the default value, or `null` if there is none.
+    ///   - `pk` — position of the column in the primary key starting at `1`, or `0`.
+    ///   - `hidden` — whether the column is a hidden column of a virtual table.
+    ///   - `generated` — whether the column is a generated column.
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.columns("users").done as Array
+    /// for column in result[1]:
+    ///     print(column.name, ": ", column.type, "" if column.nullable else " not null")
+    /// ```
+    #[func]
+    fn columns(&self, table: GString, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        self.schema(Schema::Columns(table.into()), timeout_ms)
+    }
+
+    /// Describes the indexes of a table, including those created for `UNIQUE` and
+    /// `PRIMARY KEY` constraints.
+    ///
+    /// # Parameters
+    ///
+    /// * `table` — Name of the table.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, indexes]` — `indexes` is an array with one [`VarDictionary`] per index, sorted by
+    ///   name. Its keys are:
+    ///   - `name` — name of the index.
+    ///   - `unique` — whether the index is unique.
+    ///   - `origin` — `"c"` for indexes created by `CREATE INDEX`, `"u"` for `UNIQUE`
+    ///     constraints and `"pk"` for `PRIMARY KEY` constraints.
+    ///   - `partial` — whether the index has a `WHERE` clause.
+    ///   - `columns` — array with the names of the indexed columns, `null` for expressions.
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    #[func]
+    fn indexes(&self, table: GString, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        self.schema(Schema::Indexes(table.into()), timeout_ms)
+    }
+
+    /// Describes the foreign keys of a table.
+    ///
+    /// # Parameters
+    ///
+    /// * `table` — Name of the table.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, foreign_keys]` — `foreign_keys` is an array with one [`VarDictionary`] per
+    ///   foreign key. Its keys are:
+    ///   - `table` — name of the referenced table.
+    ///   - `from` — array with the names of the referencing columns.
+    ///   - `to` — array with the names of the referenced columns, `null` where the key
+    ///     refers to the primary key of `table`.
+    ///   - `on_update` and `on_delete` — the actions of the key, such as `"CASCADE"`.
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    #[func]
+    fn foreign_keys(&self, table: GString, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        self.schema(Schema::ForeignKeys(table.into()), timeout_ms)
+    }
+
+    fn schema(&self, schema: Schema, timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        let format = Format {
+            decode: true,
+            shape: Shape::Dicts,
+        };
+        self.worker
+            .send(InputMessage::Schema(self.conn_id, task_ctx, schema, format));
+        task
+    }
+
+    /// Prepares a SQL statement for repeated execution.
+    ///
+    /// The statement is compiled once on the worker and kept there until the returned
+    /// [`AsletStatement`] is dropped, so executing it again skips parsing and planning.
+    ///
+    /// # Parameters
+    ///
+    /// * `sql` — The SQL statement to prepare.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it. Executions of the statement use
+    ///   `default_timeout_ms` unless they specify their own.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, statement]` — statement prepared successfully, `statement` is the [`AsletStatement`] instance.
+    /// * `[FAILED, code, errmsg]` — preparation failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    #[func]
+    fn prepare(&self, sql: GString, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        self.aslet
+            .bind()
+            .hold_writer(task_ctx.id(), self.worker.clone());
+        self.worker.send(InputMessage::Prepare(
             self.conn_id,
             task_ctx,
             sql.into(),
-            params.into(),
+            self.default_timeout_ms,
+            self.format(),
         ));
         task
     }
 
     /// Starts an incremental database backup.
     ///
-    /// This function creates a [`BackupRequest`] with the destination path, number of pages per step,
-    /// and a [`Callable`] for progress reporting, then sends it to the worker thread responsible for the backup.
+    /// The backup reads from this connection's own handle, so in-memory databases and
+    /// databases opened with custom options are copied as this connection sees them. Pages
+    /// are copied `step` at a time, and other work sent to the connection runs in between.
+    /// An encrypted database is copied into a file encrypted with the same key.
     ///
     /// # Parameters
     ///
     /// * `dst` — Path to the destination database.
-    /// * `step` — Number of pages to process per backup step.
+    /// * `step` — Number of pages to process per backup step, or `-1` to copy them all at once.
     /// * `progress` — A [`Callable`] that will be called periodically to report progress.
     ///   The callable **must** have the following signature:
     ///   ```gdscript
@@ -200,6 +983,12 @@ impl AsletConn {
     ///   ```
     ///   - `page_count` — total pages in the backup.
     ///   - `remaining` — pages still to be copied.
+    /// * `interval_ms` — Minimum time in milliseconds between two steps, to keep the backup
+    ///   from competing with other work on the connection. `0` runs the steps back to back.
+    ///
+    /// The returned task can be paused and resumed with [`AsletTask::pause`] and
+    /// [`AsletTask::resume`], and canceled at any step, which removes the destination
+    /// file if the backup created it.
     ///
     /// # Returns
     ///
@@ -208,19 +997,287 @@ impl AsletConn {
     ///
     /// * `[OK]` — backup done successfully.
     /// * `[FAILED, code, errmsg]` — backup failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var task := db.backup("user://autosave.db", 16, func(_total, _remaining): pass, 50)
+    /// task.pause()   # e.g. during a boss fight
+    /// task.resume()
+    /// var result := await task.done as Array
+    /// ```
+    #[func]
+    fn backup(
+        &self,
+        dst: GString,
+        step: i32,
+        progress: Callable,
+        #[opt(default = 0)] interval_ms: i64,
+    ) -> Option<Gd<AsletTask>> {
+        Some(self.copy(dst, step, progress, interval_ms, false))
+    }
+
+    /// Replaces the contents of the database with those of a database file.
+    ///
+    /// The file is copied into this connection's own handle incrementally, `step` pages at a
+    /// time, and the restored data is visible to every connection to the database once the
+    /// task completes. The task fails with `ConnectionInUse` if a cursor, prepared statement
+    /// or backup still uses the connection when the restore starts.
+    ///
+    /// Other work sent to this connection, except for the fetches run by reader threads,
+    /// waits until the restore completes, fails or is canceled, including while it is paused.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` — Path to the database file to restore from. It is opened read-only and must exist.
+    /// * `step` — Number of pages to process per step, or `-1` to copy them all at once.
+    /// * `progress` — A [`Callable`] with the same signature as the one taken by
+    ///   [`AsletConn::backup`], called periodically to report progress.
+    /// * `interval_ms` — Minimum time in milliseconds between two steps. `0` runs the steps
+    ///   back to back. The task can be paused, resumed and canceled like a backup's, and
+    ///   canceling it leaves the database as it was.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK]` — database restored successfully.
+    /// * `[FAILED, code, errmsg]` — restore failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.restore_from("user://save_backup.db", 64, func(total, remaining):
+    ///     progress_bar.value = 1.0 - float(remaining) / max(total, 1)
+    /// ).done as Array
+    /// ```
+    #[func]
+    fn restore_from(
+        &self,
+        path: GString,
+        step: i32,
+        progress: Callable,
+        #[opt(default = 0)] interval_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.copy(path, step, progress, interval_ms, true)
+    }
+
+    /// Serializes the whole database into bytes.
+    ///
+    /// The bytes are the same as the content of the database file, and can be stored or
+    /// sent elsewhere and opened again with [`Aslet::open_from_bytes`]. The database is
+    /// copied in a single step, so this is meant for small databases; use
+    /// [`AsletConn::backup`] for large ones.
+    ///
+    /// # Parameters
+    ///
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, bytes]` — `bytes` is a [`PackedByteArray`] with the serialized database.
+    /// * `[FAILED, code, errmsg]` — serialization failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.serialize().done as Array
+    /// save_data["sandbox"] = result[1]
+    /// ```
+    #[func]
+    fn serialize(&self, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        self.worker
+            .send(InputMessage::Serialize(self.conn_id, task_ctx));
+        task
+    }
+
+    /// Starts or stops emitting the changes made through this connection.
+    ///
+    /// The update, commit and rollback hooks of SQLite report every row written by this
+    /// connection and its transactions, and the signals are emitted during [`Aslet::poll`]
+    /// once the transaction that wrote them commits: [`row_changed`](Self::row_changed)
+    /// for each row, followed by [`committed`](Self::committed). A rollback emits
+    /// [`rolled_back`](Self::rolled_back) instead. Statements run outside a transaction
+    /// commit on their own.
+    ///
+    /// Changes made by other connections or to `WITHOUT ROWID` tables are not reported, and
+    /// neither are the rows changed in a savepoint before rolling back to it. Transactions
+    /// already started keep the mode they started with.
+    ///
+    /// # Parameters
+    ///
+    /// * `mode` — `CHANGES_ROWS` to report every row, `CHANGES_TABLES` to coalesce the
+    ///   changes of each transaction by table so bulk writes only emit a few signals, or
+    ///   `CHANGES_OFF` to stop tracking.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK]` — changes are tracked with the new mode.
+    /// * `[FAILED, code, errmsg]` — `mode` is invalid, or the connection is closed.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// db.row_changed.connect(func(op: int, table: String, rowid: int):
+    ///     if table == "scores":
+    ///         push_leaderboard_update(rowid)
+    /// )
+    /// await db.track_changes(AsletConn.CHANGES_ROWS).done
+    /// ```
     #[func]
-    fn backup(&self, dst: GString, step: i32, progress: Callable) -> Option<Gd<AsletTask>> {
+    fn track_changes(&self, mode: i64, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match ChangeTracking::try_from(mode) {
+            Ok(tracking) => {
+                let conn = (tracking != ChangeTracking::Off).then(|| self.base().instance_id());
+                self.aslet
+                    .bind()
+                    .track_conn(self.worker.writer_id(), self.conn_id, conn);
+                self.worker
+                    .send(InputMessage::TrackChanges(self.conn_id, task_ctx, tracking));
+            }
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Runs a query now and again whenever a table it reads from is modified.
+    ///
+    /// The rows are emitted by the returned [`AsletLiveQuery`] during [`Aslet::poll`], so
+    /// connect to its signals before polling. A transaction committed by any connection of
+    /// this [`Aslet`] to the same database that writes a table the query reads schedules it
+    /// to run again once `debounce_ms` have elapsed, so the changes committed meanwhile only
+    /// run it once.
+    ///
+    /// The query always runs on this connection, on the writer thread, and waits for a
+    /// transaction running on the connection itself to end. Changes made by other processes,
+    /// or to `WITHOUT ROWID` tables, don't run it again.
+    ///
+    /// # Parameters
+    ///
+    /// * `sql` — The SQL query to watch.
+    /// * `params` — Query parameters to bind on every run, as an array of [`Variant`] values or a
+    ///   [`VarDictionary`] keyed by parameter name (`:name`, `@name` or `$name`, prefix optional).
+    /// * `debounce_ms` — Milliseconds between the first change to a table the query reads
+    ///   and its next run.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletLiveQuery`] that emits `changed(rows, columns)` with the rows of each
+    /// run, like [`fetch`](Self::fetch) returns them, or `failed(code, errmsg)`.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var scores := db.watch("select name, score from scores order by score desc limit 10", [])
+    /// scores.changed.connect(func(rows: Array, _columns: PackedStringArray):
+    ///     leaderboard.show_rows(rows)
+    /// )
+    /// ```
+    #[func]
+    fn watch(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = 100)] debounce_ms: i64,
+    ) -> Gd<AsletLiveQuery> {
+        let aslet = self.aslet.bind();
+        let live_id = aslet.next_live_id();
+        let live = AsletLiveQuery::new(
+            self.aslet.clone(),
+            self.to_gd(),
+            live_id,
+            self.worker.clone(),
+        );
+        aslet.track_live(live_id, Some(live.instance_id()));
+
+        match Params::try_from(params) {
+            Ok(params) => self.worker.send(InputMessage::Watch(WatchRequest {
+                conn_id: self.conn_id,
+                live_id,
+                sql: sql.into(),
+                params,
+                format: self.format(),
+                debounce: Duration::from_millis(debounce_ms.max(0) as u64),
+            })),
+            Err(err) => self.worker.reject_live(live_id, err.into()),
+        }
+        live
+    }
+
+    /// Encrypts the database with a new key.
+    ///
+    /// Requires building with the `sqlcipher` feature, and a database opened with a `key`.
+    /// Connections opened for the database afterwards, such as the ones backing transactions
+    /// and reads, use the new key. It fails while a transaction or a read is in progress.
+    ///
+    /// # Parameters
+    ///
+    /// * `new_key` — The key the database is encrypted with from now on.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use
+    ///   `default_timeout_ms`, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK]` — the database was rekeyed.
+    /// * `[FAILED, code, errmsg]` — rekeying failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await db.rekey(new_key).done as Array
+    /// assert(result[0] == OK)
+    /// ```
+    #[func]
+    fn rekey(&self, new_key: GString, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        if cfg!(feature = "sqlcipher") {
+            self.worker
+                .send(InputMessage::Rekey(self.conn_id, task_ctx, new_key.into()));
+        } else {
+            self.worker
+                .reject(task_ctx, InternalError::EncryptionUnsupported.into());
+        }
+        task
+    }
+
+    fn copy(
+        &self,
+        path: GString,
+        step: i32,
+        progress: Callable,
+        interval_ms: i64,
+        restore: bool,
+    ) -> Gd<AsletTask> {
         let (task_ctx, task) = self.tasks.create(self.aslet.clone());
+        let interval = Duration::from_millis(interval_ms.max(0) as u64);
+        self.aslet.bind().watch_backup(
+            task_ctx.id(),
+            BackupWatch::new(self.worker.clone(), progress, interval),
+        );
         self.worker.send(InputMessage::BeginBackup(
             task_ctx,
             BackupRequest {
-                src: self.path.clone(),
-                dst: dst.to_string(),
+                conn_id: self.conn_id,
+                path: path.to_string(),
                 step,
-                progress,
+                restore,
             },
         ));
-        Some(task)
+        task
     }
 }
 
@@ -0,0 +1,140 @@
+use godot::prelude::*;
+
+use crate::{
+    api::{aslet::Aslet, task::AsletTask},
+    error::InternalError,
+    tasks::{Tasks, timeout},
+    types::Format,
+    worker::{Worker, messages::InputMessage},
+};
+
+/// Represents a query whose rows are read in batches.
+///
+/// The prepared statement stays alive on the worker between batches, so large result
+/// sets can be processed without holding every row in memory at once. The statement
+/// is finalized when the cursor is closed or dropped. Like any other work sent to the
+/// connection, batches wait for a transaction running on its own handle to end.
+///
+/// Tasks on the connection, batches included, aren't interrupted while the cursor is
+/// open: canceling them or exceeding their timeout lets them run to completion instead
+/// (see [`AsletTask::cancel`]).
+///
+/// While open, a cursor keeps a read transaction active on its connection. Outside of
+/// WAL mode, that prevents other connections from writing to the database, so close
+/// cursors as soon as they are no longer needed.
+#[derive(GodotClass)]
+#[class(no_init, base=RefCounted)]
+pub struct AsletCursor {
+    aslet: Gd<Aslet>,
+    // the connection the cursor reads from, whose other work its batches queue behind
+    conn_id: usize,
+    cursor_id: usize,
+    columns: PackedStringArray,
+    default_timeout_ms: i64,
+    format: Format,
+    worker: Worker,
+    tasks: Tasks,
+    closed: bool,
+}
+
+#[godot_api]
+impl AsletCursor {
+    /// Creates a new [`AsletCursor`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        aslet: Gd<Aslet>,
+        conn_id: usize,
+        cursor_id: usize,
+        columns: PackedStringArray,
+        default_timeout_ms: i64,
+        format: Format,
+        worker: Worker,
+        tasks: Tasks,
+    ) -> Gd<Self> {
+        Gd::from_object(Self {
+            aslet,
+            conn_id,
+            cursor_id,
+            columns,
+            default_timeout_ms,
+            format,
+            worker,
+            tasks,
+            closed: false,
+        })
+    }
+
+    /// Returns the names of the columns produced by the query.
+    #[func]
+    fn columns(&self) -> PackedStringArray {
+        self.columns.clone()
+    }
+
+    /// Reads the next batch of rows.
+    ///
+    /// # Parameters
+    ///
+    /// * `count` — Maximum number of rows to read.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that created the cursor, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, rows, columns]` — batch read successfully, in the same format as [`AsletConn::fetch`].
+    ///   Fewer than `count` rows means the query is exhausted, and further batches are empty.
+    /// * `[FAILED, code, errmsg]` — read failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///   The cursor can't be read after a failed batch, and further batches fail with the
+    ///   `InvalidCursor` error code.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var cursor := (await db.query("select * from events", []).done)[1] as AsletCursor
+    /// while true:
+    ///     var batch := await cursor.next_batch(1000).done as Array
+    ///     if batch[0] == FAILED or batch[1].is_empty():
+    ///         break
+    ///     export(batch[1])
+    /// cursor.close()
+    /// ```
+    #[func]
+    fn next_batch(&self, count: u32, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        // the worker hands the id of a closed cursor to the next one it opens
+        if self.closed {
+            let err = InternalError::InvalidCursor(self.cursor_id);
+            self.worker.reject(task_ctx, err.into());
+            return task;
+        }
+        self.worker.send(InputMessage::CursorNext(
+            self.conn_id,
+            task_ctx,
+            self.cursor_id,
+            count as usize,
+            self.format,
+        ));
+        task
+    }
+
+    /// Closes the cursor, finalizing its statement on the worker.
+    ///
+    /// Batches requested after closing fail with the `InvalidCursor` error code.
+    #[func]
+    fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.worker
+                .send(InputMessage::CloseCursor(self.conn_id, self.cursor_id));
+        }
+    }
+}
+
+impl Drop for AsletCursor {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,74 @@
+use godot::prelude::*;
+
+use crate::{
+    api::{aslet::Aslet, conn::AsletConn},
+    worker::{Worker, messages::InputMessage},
+};
+
+/// Represents a query that runs again whenever the tables it reads from are modified.
+///
+/// Created with [`AsletConn::watch`]. The tables are found when the query is prepared,
+/// including the ones read through views, and a transaction committed by any connection of
+/// the same [`Aslet`] to the same database that writes one of them schedules the query to
+/// run again on the writer thread of the database. The rows of each run are emitted with [`changed`](Self::changed) during
+/// [`Aslet::poll`].
+///
+/// The live query keeps its connection open, and stops running once it is stopped or dropped.
+#[derive(GodotClass)]
+#[class(no_init, base=RefCounted)]
+pub struct AsletLiveQuery {
+    aslet: Gd<Aslet>,
+    // `None` once stopped
+    conn: Option<Gd<AsletConn>>,
+    live_id: usize,
+    worker: Worker,
+    base: Base<RefCounted>,
+}
+
+#[godot_api]
+impl AsletLiveQuery {
+    /// Emitted with the rows of the query, once when it first runs and again after each
+    /// change to the tables it reads from. `rows` and `columns` are the same as the ones
+    /// returned by [`AsletConn::fetch`].
+    #[signal]
+    fn changed(rows: VarArray, columns: PackedStringArray);
+
+    /// Emitted instead of [`changed`](Self::changed) when a run of the query fails.
+    ///
+    /// A query that can't be prepared fails once and is never run again.
+    #[signal]
+    fn failed(code: i64, errmsg: GString);
+
+    /// Creates a new [`AsletLiveQuery`].
+    pub fn new(aslet: Gd<Aslet>, conn: Gd<AsletConn>, live_id: usize, worker: Worker) -> Gd<Self> {
+        Gd::from_init_fn(|base| Self {
+            aslet,
+            conn: Some(conn),
+            live_id,
+            worker,
+            base,
+        })
+    }
+
+    /// Stops running the query. Results already on their way are no longer emitted.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// func _exit_tree() -> void:
+    ///     scores.stop()
+    /// ```
+    #[func]
+    fn stop(&mut self) {
+        // the connection is released last, so it is only closed once nothing runs on it
+        if let Some(_conn) = self.conn.take() {
+            self.aslet.bind().track_live(self.live_id, None);
+            self.worker.send(InputMessage::Unwatch(self.live_id));
+        }
+    }
+}
+
+impl Drop for AsletLiveQuery {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -1,4 +1,8 @@
 pub mod aslet;
 pub mod conn;
+pub mod cursor;
+pub mod live_query;
+pub mod savepoint;
+pub mod statement;
 pub mod task;
 pub mod transaction;
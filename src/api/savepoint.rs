@@ -0,0 +1,298 @@
+use godot::prelude::*;
+
+use crate::{
+    api::{aslet::Aslet, task::AsletTask, transaction::TransactionState},
+    shape::Shape,
+    tasks::{TaskContext, Tasks, timeout},
+    types::{Format, Params},
+    worker::{Worker, messages::InputMessage},
+};
+
+/// Represents a savepoint inside a transaction.
+///
+/// A savepoint marks a point within an [`AsletTransaction`] that changes can be rolled
+/// back to, without abandoning the whole transaction. Savepoints can be nested, and
+/// expose the same `exec` and `fetch` operations as the transaction they belong to.
+///
+/// A savepoint becomes invalid once released, once a savepoint enclosing it is
+/// released or rolled back to, or once its transaction is committed or rolled back.
+/// Dropping a savepoint that is still valid rolls back and releases it.
+#[derive(GodotClass)]
+#[class(no_init, base=RefCounted)]
+pub struct AsletSavepoint {
+    aslet: Gd<Aslet>,
+    conn_id: usize,
+    name: String,
+    default_timeout_ms: i64,
+    format: Format,
+    worker: Worker,
+    state: TransactionState,
+    tasks: Tasks,
+}
+
+#[godot_api]
+impl AsletSavepoint {
+    /// Creates a new [`AsletSavepoint`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        aslet: Gd<Aslet>,
+        conn_id: usize,
+        name: String,
+        state: TransactionState,
+        default_timeout_ms: i64,
+        format: Format,
+        worker: Worker,
+        tasks: Tasks,
+    ) -> Gd<Self> {
+        Gd::from_object(Self {
+            aslet,
+            conn_id,
+            name,
+            default_timeout_ms,
+            format,
+            worker,
+            state,
+            tasks,
+        })
+    }
+
+    /// Returns the name of the savepoint.
+    #[func]
+    fn name(&self) -> GString {
+        self.name.to_godot()
+    }
+
+    /// Executes a SQL statement that does not return rows.
+    ///
+    /// See [`AsletTransaction::exec`] for the parameters and the result of the task.
+    #[func]
+    fn exec(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.create_task(timeout_ms);
+        match Params::try_from(params) {
+            Ok(params) => self.worker.send(InputMessage::Exec(
+                self.conn_id,
+                task_ctx,
+                sql.into(),
+                params,
+            )),
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Executes a SQL query and retrieves rows.
+    ///
+    /// See [`AsletTransaction::fetch`] for the parameters and the result of the task.
+    #[func]
+    fn fetch(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Rows)
+    }
+
+    /// Executes a SQL query and retrieves rows as dictionaries keyed by column name.
+    ///
+    /// See [`AsletConn::fetch_dicts`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_dicts(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Dicts)
+    }
+
+    /// Executes a SQL query that must return exactly one row.
+    ///
+    /// See [`AsletConn::fetch_one`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_one(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::One)
+    }
+
+    /// Executes a SQL query that returns at most one row.
+    ///
+    /// See [`AsletConn::fetch_optional`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_optional(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Optional)
+    }
+
+    /// Executes a SQL query and retrieves the first column of its first row.
+    ///
+    /// See [`AsletConn::fetch_value`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_value(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Value)
+    }
+
+    /// Executes a SQL query and retrieves every value of one of its columns.
+    ///
+    /// See [`AsletConn::fetch_column`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_column(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = 0)] column: u32,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Column(column as usize))
+    }
+
+    /// Executes a SQL query and retrieves its result column by column.
+    ///
+    /// See [`AsletConn::fetch_columns`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_columns(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Columns)
+    }
+
+    fn fetch_shaped(
+        &self,
+        sql: GString,
+        params: Variant,
+        timeout_ms: i64,
+        shape: Shape,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.create_task(timeout_ms);
+        match Params::try_from(params) {
+            Ok(params) => self.worker.send(InputMessage::Fetch(
+                self.conn_id,
+                task_ctx,
+                sql.into(),
+                params,
+                self.format.with_shape(shape),
+            )),
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Creates a savepoint nested in this one.
+    ///
+    /// See [`AsletTransaction::savepoint`] for the parameters and the result of the task.
+    #[func]
+    fn savepoint(&self, name: GString, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.create_task(timeout_ms);
+        self.aslet
+            .bind()
+            .hold_writer(task_ctx.id(), self.worker.clone());
+        self.worker.send(InputMessage::Savepoint(
+            task_ctx,
+            self.conn_id,
+            self.state.clone(),
+            name.into(),
+            self.default_timeout_ms,
+            self.format,
+        ));
+        task
+    }
+
+    /// Releases the savepoint, keeping its changes as part of the enclosing transaction.
+    ///
+    /// Every savepoint nested in this one is released as well, and all of them become invalid.
+    ///
+    /// # Parameters
+    ///
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that started the transaction, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK]` — savepoint released successfully.
+    /// * `[FAILED, code, errmsg]` — release failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    #[func]
+    fn release(&self, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.create_task(timeout_ms);
+        self.worker.send(InputMessage::Release(
+            task_ctx,
+            self.conn_id,
+            self.state.clone(),
+        ));
+        task
+    }
+
+    /// Reverts every change made since the savepoint was created.
+    ///
+    /// The savepoint itself stays valid and can be used again, while every savepoint
+    /// nested in it becomes invalid.
+    ///
+    /// # Parameters
+    ///
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that started the transaction, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK]` — changes rolled back successfully.
+    /// * `[FAILED, code, errmsg]` — rollback failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var sp := (await tx.savepoint("trade").done)[1] as AsletSavepoint
+    /// var result := await sp.exec("update inventory set amount = amount - 1 where id = ?1", [id]).done as Array
+    /// if result[0] == FAILED:
+    ///     await sp.rollback_to().done
+    /// await sp.release().done
+    /// ```
+    #[func]
+    fn rollback_to(&self, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.create_task(timeout_ms);
+        self.worker.send(InputMessage::RollbackTo(
+            task_ctx,
+            self.conn_id,
+            self.state.clone(),
+        ));
+        task
+    }
+
+    fn create_task(&self, timeout_ms: i64) -> (TaskContext, Gd<AsletTask>) {
+        self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        )
+    }
+}
+
+impl Drop for AsletSavepoint {
+    fn drop(&mut self) {
+        if self.state.is_active() {
+            self.rollback_to(-1);
+            self.release(-1);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use godot::prelude::*;
+
+use crate::{
+    api::{aslet::Aslet, task::AsletTask},
+    tasks::{Tasks, timeout},
+    types::{Format, Params},
+    worker::{Worker, messages::InputMessage},
+};
+
+/// Represents a statement prepared once and executed any number of times.
+///
+/// The statement is compiled on the worker when [`AsletConn::prepare`] completes and is
+/// reused by every call to [`exec`](Self::exec) and [`fetch`](Self::fetch), skipping the
+/// parsing step. It runs on the connection that prepared it and is finalized when the
+/// [`AsletStatement`] is dropped.
+#[derive(GodotClass)]
+#[class(no_init, base=RefCounted)]
+pub struct AsletStatement {
+    aslet: Gd<Aslet>,
+    conn_id: usize,
+    stmt_id: usize,
+    columns: PackedStringArray,
+    parameter_count: usize,
+    default_timeout_ms: i64,
+    format: Format,
+    worker: Worker,
+    tasks: Tasks,
+}
+
+#[godot_api]
+impl AsletStatement {
+    /// Creates a new [`AsletStatement`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        aslet: Gd<Aslet>,
+        conn_id: usize,
+        stmt_id: usize,
+        columns: PackedStringArray,
+        parameter_count: usize,
+        default_timeout_ms: i64,
+        format: Format,
+        worker: Worker,
+        tasks: Tasks,
+    ) -> Gd<Self> {
+        Gd::from_object(Self {
+            aslet,
+            conn_id,
+            stmt_id,
+            columns,
+            parameter_count,
+            default_timeout_ms,
+            format,
+            worker,
+            tasks,
+        })
+    }
+
+    /// Returns the names of the columns produced by the statement.
+    ///
+    /// Statements that don't return rows have no columns.
+    #[func]
+    fn column_names(&self) -> PackedStringArray {
+        self.columns.clone()
+    }
+
+    /// Returns the number of parameters the statement expects.
+    #[func]
+    fn parameter_count(&self) -> i64 {
+        self.parameter_count as i64
+    }
+
+    /// Executes the statement without returning rows.
+    ///
+    /// # Parameters
+    ///
+    /// * `params` — Statement parameters to bind, as an array of [`Variant`] values or a
+    ///   [`VarDictionary`] keyed by parameter name (`:name`, `@name` or `$name`, prefix optional).
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that prepared the statement, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, n, rowid]` — statement executed successfully, where `n` is the number of affected rows
+    ///   and `rowid` is the rowid of the last row inserted through the connection.
+    /// * `[FAILED, code, errmsg]` — execution failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var stmt := (await db.prepare("insert into scores (name, value) values (?1, ?2)").done)[1] as AsletStatement
+    /// for entry in entries:
+    ///     await stmt.exec([entry.name, entry.value]).done
+    /// ```
+    #[func]
+    fn exec(&self, params: Variant, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Params::try_from(params) {
+            Ok(params) => self.worker.send(InputMessage::ExecStatement(
+                self.conn_id,
+                task_ctx,
+                self.stmt_id,
+                params,
+            )),
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Executes the statement and retrieves rows.
+    ///
+    /// # Parameters
+    ///
+    /// * `params` — Query parameters to bind, as an array of [`Variant`] values or a
+    ///   [`VarDictionary`] keyed by parameter name (`:name`, `@name` or `$name`, prefix optional).
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that prepared the statement, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, rows, columns]` — query executed successfully, in the same format as [`AsletConn::fetch`].
+    /// * `[FAILED, code, errmsg]` — query failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    #[func]
+    fn fetch(&self, params: Variant, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Params::try_from(params) {
+            Ok(params) => self.worker.send(InputMessage::FetchStatement(
+                self.conn_id,
+                task_ctx,
+                self.stmt_id,
+                params,
+                self.format,
+            )),
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+}
+
+impl Drop for AsletStatement {
+    fn drop(&mut self) {
+        self.worker.send(InputMessage::CloseStatement(self.stmt_id));
+    }
+}
@@ -11,7 +11,7 @@ use crate::{
 
 /// Represents an asynchronous operation in progress.
 ///
-/// An `AsletTask` can be canceled before it completes, and will emit
+/// An `AsletTask` can be canceled before or while it runs, and will emit
 /// the [`done`] signal once finished.
 #[derive(GodotClass)]
 #[class(no_init, base=RefCounted)]
@@ -35,14 +35,58 @@ impl AsletTask {
         Gd::from_init_fn(|base| Self { aslet, ctx, base })
     }
 
-    /// Attempts to cancel the task if it is still waiting.
+    /// Attempts to cancel the task.
+    ///
+    /// A waiting task is dropped before it reaches the database. A running task has its
+    /// statement interrupted, and any partial work done by it (e.g. a `batch_insert`)
+    /// is rolled back. A backup is stopped before its next step, even while paused, and
+    /// its destination file is removed if the backup created it. In all cases the task
+    /// yields `[FAILED, code, errmsg]` with the `TaskCanceled` error code.
+    ///
+    /// The exception is a task running while a cursor is open on its connection, including
+    /// a batch of the cursor itself. SQLite interrupts every statement of the connection
+    /// at once, which would fail the cursor too, so the task runs to completion and yields
+    /// its result as usual. The same goes for a task exceeding its timeout.
     ///
     /// Returns:
     /// - `OK` if the task was successfully canceled.
-    /// - `FAILED` if the task was already running or finished.
+    /// - `FAILED` if the task has already finished.
     #[func]
     pub fn cancel(&self) -> godot::global::Error {
         if self.ctx.cancel() {
+            self.aslet.bind().wake_backup(self.ctx.id());
+            godot::global::Error::OK
+        } else {
+            godot::global::Error::FAILED
+        }
+    }
+
+    /// Pauses a backup or restore after the step in progress.
+    ///
+    /// The pages copied so far are kept, and the backup continues from there once
+    /// [`resume`](Self::resume) is called. The destination stays locked while paused.
+    ///
+    /// Returns:
+    /// - `OK` if the task is a backup or restore in progress.
+    /// - `FAILED` otherwise.
+    #[func]
+    pub fn pause(&self) -> godot::global::Error {
+        self.set_paused(true)
+    }
+
+    /// Resumes a backup or restore paused with [`pause`](Self::pause).
+    ///
+    /// Returns:
+    /// - `OK` if the task is a backup or restore in progress.
+    /// - `FAILED` otherwise.
+    #[func]
+    pub fn resume(&self) -> godot::global::Error {
+        self.set_paused(false)
+    }
+
+    fn set_paused(&self, paused: bool) -> godot::global::Error {
+        // a finished task's id may already belong to another task
+        if self.ctx.is_pending() && self.aslet.bind().pause_backup(self.ctx.id(), paused) {
             godot::global::Error::OK
         } else {
             godot::global::Error::FAILED
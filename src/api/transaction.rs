@@ -10,7 +10,10 @@ use godot::prelude::*;
 
 use crate::{
     api::{aslet::Aslet, task::AsletTask},
-    tasks::Tasks,
+    error::InternalError,
+    shape::Shape,
+    tasks::{Tasks, timeout},
+    types::{Format, Params},
     worker::{Worker, messages::InputMessage},
 };
 
@@ -37,6 +40,42 @@ impl TransactionState {
     pub fn rollback(&self) {
         self.0.store(Self::ROLLED_BACK, Ordering::Release);
     }
+
+    /// Returns `true` if both states track the same transaction or savepoint.
+    pub fn is(&self, other: &TransactionState) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// How a transaction acquires its locks, see <https://sqlite.org/lang_transaction.html>.
+#[derive(Debug, Clone, Copy)]
+pub enum TransactionMode {
+    Deferred,
+    Immediate,
+    Exclusive,
+}
+
+impl TransactionMode {
+    pub fn begin_sql(&self) -> &'static str {
+        match self {
+            TransactionMode::Deferred => "BEGIN DEFERRED TRANSACTION;",
+            TransactionMode::Immediate => "BEGIN IMMEDIATE TRANSACTION;",
+            TransactionMode::Exclusive => "BEGIN EXCLUSIVE TRANSACTION;",
+        }
+    }
+}
+
+impl TryFrom<i64> for TransactionMode {
+    type Error = InternalError;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            AsletTransaction::DEFERRED => Ok(TransactionMode::Deferred),
+            AsletTransaction::IMMEDIATE => Ok(TransactionMode::Immediate),
+            AsletTransaction::EXCLUSIVE => Ok(TransactionMode::Exclusive),
+            _ => Err(InternalError::InvalidOption("mode".to_string())),
+        }
+    }
 }
 
 /// Represents a database transaction.
@@ -44,15 +83,21 @@ impl TransactionState {
 /// A `Transaction` groups multiple operations (queries, inserts, updates)
 /// into a single unit of work. Changes can be committed or rolled back.
 ///
-/// Each `Transaction` holds its own dedicated database connection rather than
-/// sharing one. This isolation ensures that the transaction’s state remains
+/// By default each `Transaction` holds its own dedicated database connection rather
+/// than sharing one. This isolation ensures that the transaction’s state remains
 /// unaffected by asynchronous operations or concurrent interactions occurring
 /// on other connections.
+///
+/// A transaction can also run on the handle of the connection that started it,
+/// in which case other work sent to that connection waits until the transaction
+/// is committed or rolled back, except for the fetches run by reader threads.
 #[derive(GodotClass)]
 #[class(no_init, base=RefCounted)]
 pub struct AsletTransaction {
     aslet: Gd<Aslet>,
     conn_id: usize,
+    default_timeout_ms: i64,
+    format: Format,
     worker: Worker,
     state: TransactionState,
     tasks: Tasks,
@@ -60,11 +105,32 @@ pub struct AsletTransaction {
 
 #[godot_api]
 impl AsletTransaction {
+    /// Acquires no lock until the database is first read or written. This is the default.
+    #[constant]
+    pub const DEFERRED: i64 = 0;
+
+    /// Starts a write transaction immediately, failing early if another writer holds the database.
+    #[constant]
+    pub const IMMEDIATE: i64 = 1;
+
+    /// Like `IMMEDIATE`, and additionally prevents readers outside WAL mode.
+    #[constant]
+    pub const EXCLUSIVE: i64 = 2;
+
     /// Creates a new [`AsletTransaction`].
-    pub fn new(aslet: Gd<Aslet>, conn_id: usize, worker: Worker, tasks: Tasks) -> Gd<Self> {
+    pub fn new(
+        aslet: Gd<Aslet>,
+        conn_id: usize,
+        default_timeout_ms: i64,
+        format: Format,
+        worker: Worker,
+        tasks: Tasks,
+    ) -> Gd<Self> {
         Gd::from_object(Self {
             aslet,
             conn_id,
+            default_timeout_ms,
+            format,
             worker,
             tasks,
             state: TransactionState::new(),
@@ -79,13 +145,17 @@ impl AsletTransaction {
     /// # Parameters
     ///
     /// * `sql` — The SQL statement to execute.
-    /// * `params` — Statement parameters to bind, as an array of [`Variant`] values.
+    /// * `params` — Statement parameters to bind, as an array of [`Variant`] values or a
+    ///   [`VarDictionary`] keyed by parameter name (`:name`, `@name` or `$name`, prefix optional).
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that started the transaction, and `0` disables it.
     ///
     /// # Returns
     ///
     /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
     ///
-    /// * `[OK, n]` — statement executed successfully, where `n` is the number of affected rows.
+    /// * `[OK, n, rowid]` — statement executed successfully, where `n` is the number of affected rows
+    ///   and `rowid` is the rowid of the last row inserted through the connection.
     /// * `[FAILED, code, errmsg]` — execution failed. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
     ///
     /// # Example
@@ -97,14 +167,25 @@ impl AsletTransaction {
     ///     push_error(result[1])
     /// ```
     #[func]
-    fn exec(&self, sql: GString, params: Array<Variant>) -> Gd<AsletTask> {
-        let (task_ctx, task) = self.tasks.create(self.aslet.clone());
-        self.worker.send(InputMessage::Exec(
-            self.conn_id,
-            task_ctx,
-            sql.into(),
-            params.into(),
-        ));
+    fn exec(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Params::try_from(params) {
+            Ok(params) => self.worker.send(InputMessage::Exec(
+                self.conn_id,
+                task_ctx,
+                sql.into(),
+                params,
+            )),
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
         task
     }
 
@@ -116,7 +197,10 @@ impl AsletTransaction {
     /// # Parameters
     ///
     /// * `sql` — The SQL query to execute.
-    /// * `params` — Query parameters to bind, as an array of [`Variant`] values.
+    /// * `params` — Query parameters to bind, as an array of [`Variant`] values or a
+    ///   [`VarDictionary`] keyed by parameter name (`:name`, `@name` or `$name`, prefix optional).
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that started the transaction, and `0` disables it.
     ///
     /// # Returns
     ///
@@ -135,13 +219,162 @@ impl AsletTransaction {
     ///     push_error(result[1])
     /// ```
     #[func]
-    fn fetch(&self, sql: GString, params: Array<Variant>) -> Gd<AsletTask> {
-        let (task_ctx, task) = self.tasks.create(self.aslet.clone());
-        self.worker.send(InputMessage::Fetch(
-            self.conn_id,
+    fn fetch(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Rows)
+    }
+
+    /// Executes a SQL query and retrieves rows as dictionaries keyed by column name.
+    ///
+    /// See [`AsletConn::fetch_dicts`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_dicts(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Dicts)
+    }
+
+    /// Executes a SQL query that must return exactly one row.
+    ///
+    /// See [`AsletConn::fetch_one`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_one(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::One)
+    }
+
+    /// Executes a SQL query that returns at most one row.
+    ///
+    /// See [`AsletConn::fetch_optional`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_optional(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Optional)
+    }
+
+    /// Executes a SQL query and retrieves the first column of its first row.
+    ///
+    /// See [`AsletConn::fetch_value`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_value(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Value)
+    }
+
+    /// Executes a SQL query and retrieves every value of one of its columns.
+    ///
+    /// See [`AsletConn::fetch_column`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_column(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = 0)] column: u32,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Column(column as usize))
+    }
+
+    /// Executes a SQL query and retrieves its result column by column.
+    ///
+    /// See [`AsletConn::fetch_columns`] for the parameters and the result of the task.
+    #[func]
+    fn fetch_columns(
+        &self,
+        sql: GString,
+        params: Variant,
+        #[opt(default = -1)] timeout_ms: i64,
+    ) -> Gd<AsletTask> {
+        self.fetch_shaped(sql, params, timeout_ms, Shape::Columns)
+    }
+
+    fn fetch_shaped(
+        &self,
+        sql: GString,
+        params: Variant,
+        timeout_ms: i64,
+        shape: Shape,
+    ) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        match Params::try_from(params) {
+            Ok(params) => self.worker.send(InputMessage::Fetch(
+                self.conn_id,
+                task_ctx,
+                sql.into(),
+                params,
+                self.format.with_shape(shape),
+            )),
+            Err(err) => self.worker.reject(task_ctx, err.into()),
+        }
+        task
+    }
+
+    /// Creates a savepoint inside the transaction.
+    ///
+    /// Changes made after the savepoint is created can be rolled back with
+    /// [`AsletSavepoint::rollback_to`] without abandoning the whole transaction.
+    /// Savepoints track their validity the same way transactions do.
+    ///
+    /// # Parameters
+    ///
+    /// * `name` — Name of the savepoint, returned by [`AsletSavepoint::name`]. It is only used
+    ///   for display, so names don't need to be unique.
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that started the transaction, and `0` disables it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
+    ///
+    /// * `[OK, savepoint]` — savepoint created, `savepoint` is the [`AsletSavepoint`] instance.
+    /// * `[FAILED, code, errmsg]` — failed to create the savepoint. `code` is an `int` representing the error type, and `errmsg` is a `String` containing a human-readable error message.
+    ///
+    /// # Example
+    /// ```gdscript
+    /// var result := await tx.savepoint("trade").done as Array
+    /// if result[0] == OK:
+    ///     var sp := result[1] as AsletSavepoint
+    ///     await sp.exec("delete from inventory where id = ?1", [id]).done
+    ///     await sp.rollback_to().done
+    /// ```
+    #[func]
+    fn savepoint(&self, name: GString, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
+        self.aslet
+            .bind()
+            .hold_writer(task_ctx.id(), self.worker.clone());
+        self.worker.send(InputMessage::Savepoint(
             task_ctx,
-            sql.into(),
-            params.into(),
+            self.conn_id,
+            self.state.clone(),
+            name.into(),
+            self.default_timeout_ms,
+            self.format,
         ));
         task
     }
@@ -151,6 +384,11 @@ impl AsletTransaction {
     /// This function finalizes the transaction, permanently applying all changes
     /// made since it began. Once committed, the transaction becomes invalid for further use.
     ///
+    /// # Parameters
+    ///
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that started the transaction, and `0` disables it.
+    ///
     /// # Returns
     ///
     /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
@@ -167,8 +405,11 @@ impl AsletTransaction {
     ///     push_error(result[1])
     /// ```
     #[func]
-    fn commit(&self) -> Gd<AsletTask> {
-        let (task_ctx, task) = self.tasks.create(self.aslet.clone());
+    fn commit(&self, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
         self.worker.send(InputMessage::Commit(
             task_ctx,
             self.conn_id,
@@ -182,6 +423,11 @@ impl AsletTransaction {
     /// This function reverts all operations performed within the transaction,
     /// restoring the database to its previous state.
     ///
+    /// # Parameters
+    ///
+    /// * `timeout_ms` — Maximum time in milliseconds the task may take. Negative values use the
+    ///   `default_timeout_ms` of the connection that started the transaction, and `0` disables it.
+    ///
     /// # Returns
     ///
     /// Returns an [`AsletTask`] that yields **once**, producing a [`VariantArray`] with one of the following forms:
@@ -196,8 +442,11 @@ impl AsletTransaction {
     ///     push_error(result[1])
     /// ```
     #[func]
-    fn rollback(&self) -> Gd<AsletTask> {
-        let (task_ctx, task) = self.tasks.create(self.aslet.clone());
+    fn rollback(&self, #[opt(default = -1)] timeout_ms: i64) -> Gd<AsletTask> {
+        let (task_ctx, task) = self.tasks.create_with_timeout(
+            self.aslet.clone(),
+            timeout(timeout_ms, self.default_timeout_ms),
+        );
         self.worker.send(InputMessage::Rollback(
             task_ctx,
             self.conn_id,
@@ -209,6 +458,6 @@ impl AsletTransaction {
 
 impl Drop for AsletTransaction {
     fn drop(&mut self) {
-        self.rollback();
+        self.rollback(-1);
     }
 }
@@ -1,172 +1,234 @@
-use godot::{builtin::Callable, classes::ProjectSettings, meta::ToGodot, obj::Singleton};
-use rusqlite::{
-    Connection,
-    backup::{Backup, StepResult},
+use std::{
+    ffi::{CStr, c_int},
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant},
 };
 
-/// Contains all information required to initialize a [`BackupState`] on a worker thread.
-///
-/// This struct acts as a setup container for starting a database backup process.
-/// It includes the source and destination database paths, the number of pages
-/// to copy per step, and a [`Callable`] for progress reporting.
-///
-/// A `BackupRequest` is always constructed on the main thread and sent to a worker
-/// thread. The worker then consumes it to build a [`BackupState`].
-///
-/// # Thread Safety
-///
-/// `BackupRequest` implements `Send` unsafely because it holds a [`Callable`],
-/// which is not `Send` by default.  
-/// This is sound under these constraints:
-///
-/// * The struct is always **moved** between threads — never shared or referenced concurrently.
-/// * The contained [`Callable`] is not invoked or modified across threads simultaneously.
-/// * Once transferred, the main thread relinquishes ownership completely.
+use godot::{builtin::Callable, classes::ProjectSettings, obj::Singleton};
+use rusqlite::{Connection, OpenFlags, ffi};
+
+use crate::{error::Error, options::apply_key, tasks::TaskContext, worker::Worker};
+
+/// Contains all information required to start a [`BackupState`] on the worker thread.
 ///
-/// In this model, `BackupRequest` acts as an immutable setup payload safely
-/// passed to the worker thread.
+/// A `BackupRequest` is built on the main thread and sent to the worker, which
+/// consumes it to build a [`BackupState`] between the connection `conn_id` and the
+/// database file at `path`.
 ///
 /// # Fields
 ///
-/// * `src` — Path to the source database file.
-/// * `dst` — Path to the destination database file.
+/// * `conn_id` — Id of the pooled connection taking part in the copy.
+/// * `path` — Path to the database file on the other end of the copy.
 /// * `step` — Number of pages processed per backup step.
-/// * `progress` — A [`Callable`] used to report progress updates back to the main thread.
+/// * `restore` — Copy the file into the connection instead of the connection into the file.
 ///
 pub struct BackupRequest {
-    pub src: String,
-    pub dst: String,
+    pub conn_id: usize,
+    pub path: String,
     pub step: i32,
-    pub progress: Callable,
+    pub restore: bool,
 }
 
-unsafe impl Send for BackupRequest {}
+/// Progress of a backup after a step, sent back to the main thread.
+#[derive(Debug, Clone, Copy)]
+pub struct BackupProgress {
+    pub backup_id: usize,
+    pub pagecount: i32,
+    pub remaining: i32,
+    pub done: bool,
+}
 
-/// Manages an incremental SQLite database backup process across thread boundaries.
+/// Manages an incremental SQLite database backup between a pooled connection and a file.
 ///
-/// This struct holds the source and destination database connections, and the `rusqlite::backup::Backup`
-/// object, allowing for a stepped backup process. The core challenge addressed here is safely
-/// moving this state (which includes `rusqlite::Connection`s and a self-referential-like `Backup` object)
-/// between threads by leveraging `unsafe` blocks and explicit ownership transfer.
+/// The state never leaves the worker thread. It is kept there between steps, so that
+/// other work sent to the worker runs in between, and only a [`BackupProgress`] is sent
+/// back to the main thread after each step.
 ///
 /// # Safety
 ///
-/// This struct implements `Send` using an `unsafe` block. This is safe under the following critical invariants:
-///
-/// 1.  **Exclusive Ownership and Thread Safety:** An instance of `BackupState` is always moved
-///     (owned) between threads and never shared via references or accessed concurrently.
-///     The `rusqlite::Connection` type is not `Send` or `Sync` by default because `sqlite3` handles
-///     are not thread-safe for concurrent access. However, by moving `BackupState` by value,
-///     ownership of the `Connection`s (and the `Backup` object that references them) is transferred
-///     exclusively to the receiving thread, ensuring no concurrent access to the `sqlite3` handles.
-///
-/// 2.  **Lifetime Guarantees for `Backup` Object:** The `rusqlite::backup::Backup` object holds
-///     mutable references (`&'a Connection`, `&'b mut Connection`) to the `src` and `dst`
-///     connections. To store `Backup` alongside its referenced `Connection`s within the same struct,
-///     a `std::mem::transmute` is used to cast the `Backup`'s internal references to `'static`
-///     lifetimes. This "cheats" the Rust borrow checker.
-///
-///     This is safe because:
-///     *   The `BackupState` struct *owns* `src`, `dst`, and `backup`, ensuring they are
-///         co-located and dropped together.
-///     *   Rust's drop order guarantees that fields are dropped in declaration order.
-///         In `BackupState`, `backup` is declared *before* `src` and `dst`.
-///         This ensures that `backup` is dropped *before* `src` and `dst`.
-///     *   Crucially, `rusqlite::backup::Backup::drop` performs a significant operation:
-///         it calls `sqlite3_backup_finish`. During this `drop` operation, the `backup` object
-///         requires its internal references to `src` and `dst` to still be valid.
-///     *   Since `src` and `dst` are only dropped *after* `backup` has been dropped, their
-///         validity is guaranteed during the entire lifecycle of `backup`, including its `drop`
-///         implementation.
-///
-/// 3.  **Heap allocation via `Box<Connection>`:** The `Connection`s are placed on the heap
-///     using `Box` to provide stable memory addresses. This ensures that the references
-///     held internally by the `Backup` object remain valid for its entire lifetime.
-///
-#[allow(unused)]
+/// The backup is driven through the `sqlite3_backup_*` functions directly, because
+/// `rusqlite::backup::Backup` requires a `&mut Connection` for the destination, which the
+/// pooled connection, shared through an `Rc`, can't provide when restoring into it.
+/// This is sound because:
+///
+/// * `handle` is finished in `Drop` before either connection is released: `conn` keeps
+///   the pooled connection open even if it is closed meanwhile, and `file` is only
+///   dropped after `drop` returns.
+/// * The struct never leaves the worker thread, which is also the only thread using
+///   `conn`, so the connections are never used concurrently.
+/// * SQLite allows the source connection to be used while the backup is in progress,
+///   and a failed step leaves the destination in a state that `sqlite3_backup_finish`
+///   cleans up.
 pub struct BackupState {
-    backup: Backup<'static, 'static>,
-    src: Box<Connection>,
-    dst: Box<Connection>,
+    handle: *mut ffi::sqlite3_backup,
+    file: Connection,
+    conn: Rc<Connection>,
+    conn_id: usize,
+    path: String,
+    created: bool,
     step: i32,
-    done: bool,
-    progress: Callable,
+    restore: bool,
 }
 
-unsafe impl Send for BackupState {}
-
 impl BackupState {
-    pub fn new(state: BackupRequest) -> Result<Self, rusqlite::Error> {
-        let src_path = ProjectSettings::singleton()
-            .globalize_path(&state.src)
-            .to_string();
-        let dst_path = ProjectSettings::singleton()
-            .globalize_path(&state.dst)
+    /// Starts the copy, opening the file with `key` when the connection is encrypted,
+    /// since SQLCipher only copies pages between databases sharing the same key.
+    pub fn new(
+        conn: Rc<Connection>,
+        request: BackupRequest,
+        key: Option<String>,
+    ) -> Result<Self, Error> {
+        let path = ProjectSettings::singleton()
+            .globalize_path(&request.path)
             .to_string();
+        let created = !request.restore && !Path::new(&path).exists();
+        // a missing file would otherwise be created and restored as an empty database
+        let file = if request.restore {
+            Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?
+        } else {
+            Connection::open(&path)?
+        };
+        if let Some(key) = key {
+            apply_key(&file, &key)?;
+        }
 
-        let src_conn = Box::new(Connection::open(&src_path)?);
-        let mut dst_conn = Box::new(Connection::open(&dst_path)?);
-        let backup = unsafe {
-            let src_ref: *const Connection = &*src_conn;
-            let dst_ref: *mut Connection = &mut *dst_conn;
-            let backup = Backup::new(&*src_ref, &mut *dst_ref)?;
-            std::mem::transmute::<Backup<'_, '_>, Backup<'static, 'static>>(backup)
+        let (src, dst) = if request.restore {
+            (&file, conn.as_ref())
+        } else {
+            (conn.as_ref(), &file)
+        };
+        let handle = unsafe {
+            let dst = dst.handle();
+            let main = c"main".as_ptr();
+            let handle = ffi::sqlite3_backup_init(dst, main, src.handle(), main);
+            if handle.is_null() {
+                return Err(error_from_handle(dst, ffi::sqlite3_errcode(dst)).into());
+            }
+            handle
         };
 
         Ok(Self {
-            src: src_conn,
-            dst: dst_conn,
-            backup,
-            step: state.step,
-            done: false,
-            progress: state.progress,
+            handle,
+            file,
+            conn,
+            conn_id: request.conn_id,
+            path,
+            created,
+            step: request.step,
+            restore: request.restore,
         })
     }
 
-    pub fn worker<'a>(&'a mut self) -> Worker<'a> {
-        Worker(self)
+    /// Copies up to `step` pages, returning `true` once every page was copied.
+    ///
+    /// A step that finds the database busy or locked copies nothing and is retried
+    /// by the next one.
+    pub fn step(&mut self) -> Result<bool, rusqlite::Error> {
+        match unsafe { ffi::sqlite3_backup_step(self.handle, self.step) } {
+            ffi::SQLITE_DONE => Ok(true),
+            ffi::SQLITE_OK | ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => Ok(false),
+            code => Err(unsafe { error_from_handle(self.destination().handle(), code) }),
+        }
     }
 
-    pub fn main<'a>(&'a self) -> Main<'a> {
-        Main(self)
+    /// Returns the total number of pages and the number of pages still to be copied.
+    pub fn progress(&self) -> (i32, i32) {
+        unsafe {
+            (
+                ffi::sqlite3_backup_pagecount(self.handle),
+                ffi::sqlite3_backup_remaining(self.handle),
+            )
+        }
     }
-}
 
-/// Provides the worker-side API for performing incremental backup steps.
-///
-/// A [`Worker`] instance is created from a mutable reference to a [`BackupState`]
-/// by calling [`BackupState::worker`]. It holds a mutable reference of the state,
-/// ensuring that only the worker can mutate the backup progress while the borrow exists.
-///
-pub struct Worker<'a>(&'a mut BackupState);
+    /// Returns the id of the connection being restored, if the backup is a restore.
+    pub fn restoring(&self) -> Option<usize> {
+        self.restore.then_some(self.conn_id)
+    }
 
-impl<'a> Worker<'a> {
-    pub fn step(&mut self) -> Result<bool, rusqlite::Error> {
-        if let StepResult::Done = self.0.backup.step(self.0.step)? {
-            self.0.done = true;
+    /// Abandons the backup, removing the destination file if the backup created it.
+    ///
+    /// SQLite rolls back the pages copied so far, so a restored database, or a file that
+    /// existed before the backup started, keeps its previous contents.
+    pub fn discard(self) {
+        let path = self.created.then(|| self.path.clone());
+        drop(self);
+        if let Some(path) = path {
+            let _ = std::fs::remove_file(path);
         }
-        Ok(self.0.done)
+    }
+
+    fn destination(&self) -> &Connection {
+        if self.restore { &self.conn } else { &self.file }
     }
 }
 
-/// Provides the main-thread API for monitoring backup progress.
-///
-/// A [`Main`] view is obtained by calling [`BackupState::main`].
-/// It borrows the state immutably, giving read-only access to
-/// completion status and progress metrics.
-///
-pub struct Main<'a>(&'a BackupState);
-
-impl<'a> Main<'a> {
-    pub fn tick(&self) {
-        let progress = self.0.backup.progress();
-        self.0.progress.call(&[
-            progress.pagecount.to_variant(),
-            progress.remaining.to_variant(),
-        ]);
+impl Drop for BackupState {
+    fn drop(&mut self) {
+        unsafe { ffi::sqlite3_backup_finish(self.handle) };
+    }
+}
+
+/// Main-thread side of a backup, reporting its progress and deciding when its next
+/// step is sent to the worker.
+pub struct BackupWatch {
+    worker: Worker,
+    progress: Callable,
+    interval: Duration,
+    paused: bool,
+    next_step: Option<(TaskContext, usize, Instant)>,
+}
+
+impl BackupWatch {
+    pub fn new(worker: Worker, progress: Callable, interval: Duration) -> Self {
+        Self {
+            worker,
+            progress,
+            interval,
+            paused: false,
+            next_step: None,
+        }
+    }
+
+    /// Returns the handle to the writer thread of the connection being copied.
+    pub fn worker(&self) -> &Worker {
+        &self.worker
+    }
+
+    pub fn progress(&self) -> &Callable {
+        &self.progress
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Schedules the step after `backup_id`'s last one, once the interval has elapsed.
+    pub fn schedule(&mut self, task_ctx: TaskContext, backup_id: usize) {
+        self.next_step = Some((task_ctx, backup_id, Instant::now() + self.interval));
     }
 
-    pub fn is_done(&self) -> bool {
-        self.0.done
+    /// Takes the scheduled step if it is due and the backup isn't paused, or right
+    /// away with `force` or once the task is canceled, so the worker can clean up.
+    pub fn take_due(&mut self, now: Instant, force: bool) -> Option<(TaskContext, usize)> {
+        match &self.next_step {
+            Some((task_ctx, _, due))
+                if force || task_ctx.is_canceled() || (!self.paused && *due <= now) =>
+            {
+                self.next_step
+                    .take()
+                    .map(|(task_ctx, backup_id, _)| (task_ctx, backup_id))
+            }
+            _ => None,
+        }
     }
 }
+
+unsafe fn error_from_handle(db: *mut ffi::sqlite3, code: c_int) -> rusqlite::Error {
+    let msg = unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(db)) };
+    rusqlite::Error::SqliteFailure(
+        ffi::Error::new(code),
+        Some(msg.to_string_lossy().into_owned()),
+    )
+}
@@ -0,0 +1,439 @@
+//! Encoding of Godot types that SQLite has no storage class for.
+//!
+//! Vectors, colors, rects and transforms are stored as JSON arrays of their
+//! components, dictionaries and arrays as `JSON.stringify` text, and packed arrays
+//! as little-endian BLOBs. The exact layouts are listed in the README's
+//! "Type Mapping" section. [`decode`] reverses every encoding, given the type the
+//! value was stored from.
+
+use std::collections::HashMap;
+
+use godot::{
+    classes::{Json, Time},
+    prelude::*,
+};
+
+use crate::types::{Columns, Value};
+
+/// Encodes a vector, color, rect, transform, dictionary, array or packed array,
+/// or returns `None` for any other type.
+pub fn encode(value: &Variant) -> Option<Value> {
+    let text = match value.get_type() {
+        VariantType::VECTOR2 => json(&vec2(value.to())),
+        VariantType::VECTOR2I => {
+            let v = value.to::<Vector2i>();
+            json_ints(&[v.x, v.y])
+        }
+        VariantType::VECTOR3 => json(&vec3(value.to())),
+        VariantType::VECTOR3I => {
+            let v = value.to::<Vector3i>();
+            json_ints(&[v.x, v.y, v.z])
+        }
+        VariantType::VECTOR4 => json(&vec4(value.to())),
+        VariantType::VECTOR4I => {
+            let v = value.to::<Vector4i>();
+            json_ints(&[v.x, v.y, v.z, v.w])
+        }
+        VariantType::COLOR => json(&color(value.to())),
+        VariantType::RECT2 => json(&rect2(value.to())),
+        VariantType::RECT2I => {
+            let r = value.to::<Rect2i>();
+            json_ints(&[r.position.x, r.position.y, r.size.x, r.size.y])
+        }
+        VariantType::TRANSFORM2D => {
+            let t = value.to::<Transform2D>();
+            json(&[vec2(t.a), vec2(t.b), vec2(t.origin)].concat())
+        }
+        VariantType::TRANSFORM3D => {
+            let t = value.to::<Transform3D>();
+            let b = t.basis;
+            json(
+                &[
+                    vec3(b.col_a()),
+                    vec3(b.col_b()),
+                    vec3(b.col_c()),
+                    vec3(t.origin),
+                ]
+                .concat(),
+            )
+        }
+        VariantType::DICTIONARY | VariantType::ARRAY => Json::stringify(value).to_string(),
+        _ => return encode_packed(value).map(Value::Blob),
+    };
+    Some(Value::String(text))
+}
+
+fn encode_packed(value: &Variant) -> Option<Vec<u8>> {
+    Some(match value.get_type() {
+        VariantType::PACKED_INT32_ARRAY => {
+            le_bytes(value.to::<PackedInt32Array>().as_slice(), |v| {
+                v.to_le_bytes()
+            })
+        }
+        VariantType::PACKED_INT64_ARRAY => {
+            le_bytes(value.to::<PackedInt64Array>().as_slice(), |v| {
+                v.to_le_bytes()
+            })
+        }
+        VariantType::PACKED_FLOAT32_ARRAY => {
+            le_bytes(value.to::<PackedFloat32Array>().as_slice(), |v| {
+                v.to_le_bytes()
+            })
+        }
+        VariantType::PACKED_FLOAT64_ARRAY => {
+            le_bytes(value.to::<PackedFloat64Array>().as_slice(), |v| {
+                v.to_le_bytes()
+            })
+        }
+        VariantType::PACKED_VECTOR2_ARRAY => {
+            f32_bytes(value.to::<PackedVector2Array>().as_slice(), |v| vec2(*v))
+        }
+        VariantType::PACKED_VECTOR3_ARRAY => {
+            f32_bytes(value.to::<PackedVector3Array>().as_slice(), |v| vec3(*v))
+        }
+        VariantType::PACKED_VECTOR4_ARRAY => {
+            f32_bytes(value.to::<PackedVector4Array>().as_slice(), |v| vec4(*v))
+        }
+        VariantType::PACKED_COLOR_ARRAY => {
+            f32_bytes(value.to::<PackedColorArray>().as_slice(), |v| color(*v))
+        }
+        VariantType::PACKED_STRING_ARRAY => {
+            let mut bytes = Vec::new();
+            for s in value.to::<PackedStringArray>().as_slice() {
+                bytes.extend_from_slice(s.to_string().as_bytes());
+                bytes.push(0);
+            }
+            bytes
+        }
+        _ => return None,
+    })
+}
+
+/// Decodes `value`, as returned by a query, back into a Variant of type `ty`.
+///
+/// Besides the encodings produced by [`encode`], integers decode to `bool`
+/// and text to `StringName` or `NodePath`. Returns `None` if `value` doesn't hold
+/// a valid encoding of `ty`.
+pub fn decode(value: &Variant, ty: VariantType) -> Option<Variant> {
+    match value.get_type() {
+        VariantType::INT => match ty {
+            VariantType::BOOL => Some((value.to::<i64>() != 0).to_variant()),
+            VariantType::INT => Some(value.clone()),
+            _ => None,
+        },
+        VariantType::STRING => decode_text(&value.to::<GString>().to_string(), ty),
+        VariantType::PACKED_BYTE_ARRAY => decode_blob(value.to::<PackedByteArray>().as_slice(), ty),
+        _ if value.get_type() == ty => Some(value.clone()),
+        _ => None,
+    }
+}
+
+fn decode_text(text: &str, ty: VariantType) -> Option<Variant> {
+    let variant = match ty {
+        VariantType::STRING => text.to_variant(),
+        VariantType::STRING_NAME => StringName::from(text).to_variant(),
+        VariantType::NODE_PATH => NodePath::from(text).to_variant(),
+        VariantType::DICTIONARY | VariantType::ARRAY => {
+            let parsed = Json::parse_string(text);
+            if parsed.get_type() != ty {
+                return None;
+            }
+            parsed
+        }
+        VariantType::VECTOR2I
+        | VariantType::VECTOR3I
+        | VariantType::VECTOR4I
+        | VariantType::RECT2I => {
+            let n = int_components(text)?;
+            match (ty, n.as_slice()) {
+                (VariantType::VECTOR2I, &[x, y]) => Vector2i::new(x, y).to_variant(),
+                (VariantType::VECTOR3I, &[x, y, z]) => Vector3i::new(x, y, z).to_variant(),
+                (VariantType::VECTOR4I, &[x, y, z, w]) => Vector4i::new(x, y, z, w).to_variant(),
+                (VariantType::RECT2I, &[x, y, w, h]) => {
+                    Rect2i::from_components(x, y, w, h).to_variant()
+                }
+                _ => return None,
+            }
+        }
+        _ => {
+            let n = components(text)?;
+            match (ty, n.as_slice()) {
+                (VariantType::VECTOR2, &[x, y]) => Vector2::new(x, y).to_variant(),
+                (VariantType::VECTOR3, &[x, y, z]) => Vector3::new(x, y, z).to_variant(),
+                (VariantType::VECTOR4, &[x, y, z, w]) => Vector4::new(x, y, z, w).to_variant(),
+                (VariantType::COLOR, &[r, g, b, a]) => Color::from_rgba(r, g, b, a).to_variant(),
+                (VariantType::RECT2, &[x, y, w, h]) => {
+                    Rect2::from_components(x, y, w, h).to_variant()
+                }
+                (VariantType::TRANSFORM2D, &[ax, ay, bx, by, ox, oy]) => Transform2D::from_cols(
+                    Vector2::new(ax, ay),
+                    Vector2::new(bx, by),
+                    Vector2::new(ox, oy),
+                )
+                .to_variant(),
+                (VariantType::TRANSFORM3D, n) if n.len() == 12 => Transform3D::from_cols(
+                    Vector3::new(n[0], n[1], n[2]),
+                    Vector3::new(n[3], n[4], n[5]),
+                    Vector3::new(n[6], n[7], n[8]),
+                    Vector3::new(n[9], n[10], n[11]),
+                )
+                .to_variant(),
+                _ => return None,
+            }
+        }
+    };
+    Some(variant)
+}
+
+fn decode_blob(bytes: &[u8], ty: VariantType) -> Option<Variant> {
+    let variant = match ty {
+        VariantType::PACKED_BYTE_ARRAY => PackedByteArray::from(bytes).to_variant(),
+        VariantType::PACKED_INT32_ARRAY => {
+            PackedInt32Array::from(from_le(bytes, i32::from_le_bytes)?.as_slice()).to_variant()
+        }
+        VariantType::PACKED_INT64_ARRAY => {
+            PackedInt64Array::from(from_le(bytes, i64::from_le_bytes)?.as_slice()).to_variant()
+        }
+        VariantType::PACKED_FLOAT32_ARRAY => {
+            PackedFloat32Array::from(from_le(bytes, f32::from_le_bytes)?.as_slice()).to_variant()
+        }
+        VariantType::PACKED_FLOAT64_ARRAY => {
+            PackedFloat64Array::from(from_le(bytes, f64::from_le_bytes)?.as_slice()).to_variant()
+        }
+        VariantType::PACKED_VECTOR2_ARRAY => {
+            let items = from_f32(bytes, |c: [f32; 2]| {
+                Vector2::new(c[0] as real, c[1] as real)
+            })?;
+            PackedVector2Array::from(items.as_slice()).to_variant()
+        }
+        VariantType::PACKED_VECTOR3_ARRAY => {
+            let items = from_f32(bytes, |c: [f32; 3]| {
+                Vector3::new(c[0] as real, c[1] as real, c[2] as real)
+            })?;
+            PackedVector3Array::from(items.as_slice()).to_variant()
+        }
+        VariantType::PACKED_VECTOR4_ARRAY => {
+            let items = from_f32(bytes, |c: [f32; 4]| {
+                Vector4::new(c[0] as real, c[1] as real, c[2] as real, c[3] as real)
+            })?;
+            PackedVector4Array::from(items.as_slice()).to_variant()
+        }
+        VariantType::PACKED_COLOR_ARRAY => {
+            let items = from_f32(bytes, |c: [f32; 4]| {
+                Color::from_rgba(c[0], c[1], c[2], c[3])
+            })?;
+            PackedColorArray::from(items.as_slice()).to_variant()
+        }
+        // an empty array encodes to an empty blob, which has no trailing NUL to strip
+        VariantType::PACKED_STRING_ARRAY if bytes.is_empty() => {
+            PackedStringArray::new().to_variant()
+        }
+        VariantType::PACKED_STRING_ARRAY => {
+            let body = bytes.strip_suffix(&[0])?;
+            let items: Vec<GString> = body
+                .split(|b| *b == 0)
+                .map(|s| GString::from(String::from_utf8_lossy(s).as_ref()))
+                .collect();
+            PackedStringArray::from(items.as_slice()).to_variant()
+        }
+        _ => return None,
+    };
+    Some(variant)
+}
+
+/// Declared column types whose values are decoded into other Godot types, used
+/// when a connection has `decode_types` enabled.
+///
+/// Besides the types registered by the user, `BOOLEAN` and `BOOL` decode to
+/// `bool`, `JSON` to a `Dictionary` or `Array`, and `DATETIME`, `TIMESTAMP` and
+/// `DATE` text to a datetime dictionary. Registering one of these names overrides it.
+#[derive(Debug, Default)]
+pub struct DeclaredTypes(HashMap<String, VariantType>);
+
+enum Declared {
+    Registered(VariantType),
+    Bool,
+    Json,
+    DateTime,
+}
+
+impl DeclaredTypes {
+    pub fn register(&mut self, decltype: &str, ty: VariantType) {
+        self.0.insert(normalize(decltype), ty);
+    }
+
+    /// Returns a [`Decoder`] for the columns of a query.
+    pub fn decoder(&self, columns: &Columns) -> Decoder {
+        Decoder(
+            columns
+                .decltypes()
+                .iter()
+                .map(|decltype| decltype.as_deref().and_then(|d| self.resolve(d)))
+                .collect(),
+        )
+    }
+
+    fn resolve(&self, decltype: &str) -> Option<Declared> {
+        let name = normalize(decltype);
+        if let Some(ty) = self.0.get(&name) {
+            return Some(Declared::Registered(*ty));
+        }
+        match name.as_str() {
+            "BOOLEAN" | "BOOL" => Some(Declared::Bool),
+            "JSON" => Some(Declared::Json),
+            "DATETIME" | "TIMESTAMP" | "DATE" => Some(Declared::DateTime),
+            _ => None,
+        }
+    }
+}
+
+/// Converts the values of a query for GDScript, decoding those in columns with a
+/// known declared type.
+///
+/// Values that aren't a valid encoding of their declared type are left as they are.
+pub struct Decoder(Vec<Option<Declared>>);
+
+impl Decoder {
+    /// A decoder that converts every value by its storage class only.
+    pub fn none() -> Self {
+        Self(vec![])
+    }
+
+    /// Returns `true` if values of `column` are decoded by its declared type.
+    pub fn is_declared(&self, column: usize) -> bool {
+        matches!(self.0.get(column), Some(Some(_)))
+    }
+
+    pub fn decode(&self, column: usize, value: &Value) -> Variant {
+        let variant = value.to_variant();
+        match self.0.get(column) {
+            Some(Some(declared)) => declared.decode(&variant).unwrap_or(variant),
+            _ => variant,
+        }
+    }
+}
+
+impl Declared {
+    fn decode(&self, value: &Variant) -> Option<Variant> {
+        match self {
+            Declared::Registered(ty) => decode(value, *ty),
+            Declared::Bool => decode(value, VariantType::BOOL),
+            Declared::Json => {
+                let text = value.try_to::<GString>().ok()?;
+                let parsed = Json::parse_string(&text);
+                matches!(
+                    parsed.get_type(),
+                    VariantType::DICTIONARY | VariantType::ARRAY
+                )
+                .then_some(parsed)
+            }
+            Declared::DateTime => {
+                let text = value.try_to::<GString>().ok()?;
+                let dict = Time::singleton().get_datetime_dict_from_datetime_string(&text, false);
+                (!dict.is_empty()).then(|| dict.to_variant())
+            }
+        }
+    }
+}
+
+/// Reduces a declared type such as `varchar(20)` to its uppercase name, `VARCHAR`.
+fn normalize(decltype: &str) -> String {
+    let name = decltype.split('(').next().unwrap_or_default();
+    name.trim().to_ascii_uppercase()
+}
+
+fn vec2(v: Vector2) -> Vec<f64> {
+    vec![v.x as f64, v.y as f64]
+}
+
+fn vec3(v: Vector3) -> Vec<f64> {
+    vec![v.x as f64, v.y as f64, v.z as f64]
+}
+
+fn vec4(v: Vector4) -> Vec<f64> {
+    vec![v.x as f64, v.y as f64, v.z as f64, v.w as f64]
+}
+
+fn color(c: Color) -> Vec<f64> {
+    vec![c.r as f64, c.g as f64, c.b as f64, c.a as f64]
+}
+
+fn rect2(r: Rect2) -> Vec<f64> {
+    [vec2(r.position), vec2(r.size)].concat()
+}
+
+fn json(components: &[f64]) -> String {
+    let items: Vec<String> = components.iter().map(|c| c.to_string()).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Writes integer components exactly, since `real` can't hold every `i32`.
+fn json_ints(components: &[i32]) -> String {
+    let items: Vec<String> = components.iter().map(|c| c.to_string()).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Parses a JSON array of numbers, as written by [`json`].
+fn components(text: &str) -> Option<Vec<real>> {
+    let inner = text.trim().strip_prefix('[')?.strip_suffix(']')?;
+    inner
+        .split(',')
+        .map(|c| c.trim().parse::<f64>().ok().map(|c| c as real))
+        .collect()
+}
+
+/// Parses a JSON array of integers, as written by [`json_ints`], failing on any
+/// component that doesn't fit in an `i32`.
+fn int_components(text: &str) -> Option<Vec<i32>> {
+    let inner = text.trim().strip_prefix('[')?.strip_suffix(']')?;
+    inner
+        .split(',')
+        .map(|c| i32::try_from(c.trim().parse::<i64>().ok()?).ok())
+        .collect()
+}
+
+fn le_bytes<T, const N: usize>(items: &[T], to_bytes: impl Fn(&T) -> [u8; N]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(items.len() * N);
+    for item in items {
+        bytes.extend_from_slice(&to_bytes(item));
+    }
+    bytes
+}
+
+fn f32_bytes<T>(items: &[T], to_components: impl Fn(&T) -> Vec<f64>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for item in items {
+        for c in to_components(item) {
+            bytes.extend_from_slice(&(c as f32).to_le_bytes());
+        }
+    }
+    bytes
+}
+
+fn from_le<T, const N: usize>(bytes: &[u8], from_bytes: impl Fn([u8; N]) -> T) -> Option<Vec<T>> {
+    if !bytes.len().is_multiple_of(N) {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(N)
+            .map(|chunk| from_bytes(chunk.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+fn from_f32<T, const N: usize>(
+    bytes: &[u8],
+    from_components: impl Fn([f32; N]) -> T,
+) -> Option<Vec<T>> {
+    let floats = from_le(bytes, f32::from_le_bytes)?;
+    if !floats.len().is_multiple_of(N) {
+        return None;
+    }
+    Some(
+        floats
+            .chunks_exact(N)
+            .map(|chunk| from_components(chunk.try_into().unwrap()))
+            .collect(),
+    )
+}
@@ -11,7 +11,21 @@ pub enum InternalError {
     InvalidTransaction,
     TaskCanceled,
     Unreachable,
-    UnsupportedVariantType(VariantType),
+    /// A value that can't be bound, along with where it was given, such as
+    /// `parameter 2` or `row 3, parameter "name"`.
+    UnsupportedVariantType(String, VariantType),
+    Timeout,
+    InvalidOption(String),
+    InvalidCursor(usize),
+    InvalidStatement(usize),
+    FileNotReadable(String, godot::global::Error),
+    InvalidMigration(String),
+    MigrationModified(i64),
+    WrongKey,
+    EncryptionUnsupported,
+    /// Parameters given as something other than an array or a dictionary.
+    InvalidParams(String, VariantType),
+    ConnectionInUse(usize),
 }
 
 impl From<&InternalError> for i64 {
@@ -22,7 +36,18 @@ impl From<&InternalError> for i64 {
                 InternalError::InvalidTransaction => 2,
                 InternalError::TaskCanceled => 3,
                 InternalError::Unreachable => 4,
-                InternalError::UnsupportedVariantType(_) => 5,
+                InternalError::UnsupportedVariantType(..) => 5,
+                InternalError::Timeout => 6,
+                InternalError::InvalidOption(_) => 7,
+                InternalError::InvalidCursor(_) => 8,
+                InternalError::InvalidStatement(_) => 9,
+                InternalError::FileNotReadable(..) => 10,
+                InternalError::InvalidMigration(_) => 11,
+                InternalError::MigrationModified(_) => 12,
+                InternalError::WrongKey => 13,
+                InternalError::EncryptionUnsupported => 14,
+                InternalError::InvalidParams(..) => 15,
+                InternalError::ConnectionInUse(_) => 16,
             }
     }
 }
@@ -36,8 +61,40 @@ impl Display for InternalError {
             InternalError::InvalidTransaction => write!(f, "invalid transaction"),
             InternalError::TaskCanceled => write!(f, "task canceled"),
             InternalError::Unreachable => write!(f, "unreachable"),
-            InternalError::UnsupportedVariantType(ty) => {
-                write!(f, "unsupported variant type: {:?}", ty)
+            InternalError::UnsupportedVariantType(param, ty) => {
+                write!(f, "unsupported variant type {:?} at {}", ty, param)
+            }
+            InternalError::Timeout => write!(f, "task timed out"),
+            InternalError::InvalidOption(key) => write!(f, "invalid option: {}", key),
+            InternalError::InvalidCursor(cursor_id) => {
+                write!(f, "invalid cursor id: {}", cursor_id)
+            }
+            InternalError::InvalidStatement(stmt_id) => {
+                write!(f, "invalid statement id: {}", stmt_id)
+            }
+            InternalError::FileNotReadable(path, err) => {
+                write!(f, "could not read file {}: {:?}", path, err)
+            }
+            InternalError::InvalidMigration(reason) => write!(f, "invalid migration: {}", reason),
+            InternalError::MigrationModified(version) => {
+                write!(f, "migration {} was modified after being applied", version)
+            }
+            InternalError::WrongKey => write!(f, "wrong key, or the database is not encrypted"),
+            InternalError::EncryptionUnsupported => {
+                write!(
+                    f,
+                    "encryption requires building aslet with the sqlcipher feature"
+                )
+            }
+            InternalError::InvalidParams(what, ty) => {
+                write!(f, "{} must be an Array or a Dictionary, not {:?}", what, ty)
+            }
+            InternalError::ConnectionInUse(conn_id) => {
+                write!(
+                    f,
+                    "connection {} is in use by a cursor, statement or backup",
+                    conn_id
+                )
             }
         }
     }
@@ -47,6 +104,11 @@ impl Display for InternalError {
 pub enum Error {
     Internal(InternalError),
     Sqlite(rusqlite::Error),
+    /// An error raised by one of the statements of a script, along with the index of
+    /// that statement and the byte offset of the offending token, or `-1` if unknown.
+    Script(usize, i64, Box<Error>),
+    /// An error raised while applying the migration with the given version.
+    Migration(i64, Box<Error>),
 }
 
 impl From<&Error> for i64 {
@@ -57,6 +119,7 @@ impl From<&Error> for i64 {
                 rusqlite::Error::SqliteFailure(e, _) => e.extended_code as i64,
                 err => RUSQLITE + rusqlite_error_to_int(err),
             },
+            Error::Script(_, _, error) | Error::Migration(_, error) => error.as_ref().into(),
         }
     }
 }
@@ -66,6 +129,14 @@ impl Display for Error {
         match self {
             Error::Internal(error) => error.fmt(f),
             Error::Sqlite(error) => error.fmt(f),
+            Error::Script(index, offset, error) => match error.as_ref() {
+                // the SQL carried by the error is the remainder of the script, too long to repeat
+                Error::Sqlite(rusqlite::Error::SqlInputError { msg, .. }) => {
+                    write!(f, "statement {} at offset {}: {}", index, offset, msg)
+                }
+                error => write!(f, "statement {}: {}", index, error),
+            },
+            Error::Migration(version, error) => write!(f, "migration {}: {}", version, error),
         }
     }
 }
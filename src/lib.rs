@@ -1,7 +1,11 @@
 mod api;
 mod backup;
+mod codec;
 mod error;
+mod migration;
+mod options;
 mod result;
+mod shape;
 mod tasks;
 mod types;
 mod worker;
@@ -0,0 +1,100 @@
+use godot::{
+    classes::{DirAccess, FileAccess, HashingContext, hashing_context::HashType},
+    prelude::*,
+};
+
+use crate::error::InternalError;
+
+/// A versioned SQL script applied by `AsletConn.migrate` and `AsletConn.migrate_dir`.
+///
+/// Migrations are built on the main thread, where their files are read and their
+/// checksums computed, and then sent to the worker that applies them.
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub sql: String,
+    pub checksum: String,
+}
+
+impl Migration {
+    pub fn new(version: i64, name: String, sql: String) -> Self {
+        let checksum = checksum(&sql);
+        Self {
+            version,
+            name,
+            sql,
+            checksum,
+        }
+    }
+
+    /// Builds migrations from an array of SQL scripts, numbered from `1` in array order.
+    pub fn from_array(scripts: Array<Variant>) -> Result<Vec<Self>, InternalError> {
+        scripts
+            .iter_shared()
+            .enumerate()
+            .map(|(i, script)| {
+                let version = i as i64 + 1;
+                match script.try_to::<GString>() {
+                    Ok(sql) => Ok(Self::new(version, String::new(), sql.into())),
+                    Err(_) => Err(InternalError::InvalidMigration(format!(
+                        "migration {} is not a string",
+                        version
+                    ))),
+                }
+            })
+            .collect()
+    }
+
+    /// Loads every `.sql` file in `dir`, taking the version from the digits its name
+    /// starts with, so `0002_add_scores.sql` becomes version `2` named `add_scores`.
+    pub fn from_dir(dir: &GString) -> Result<Vec<Self>, InternalError> {
+        let files = DirAccess::get_files_at(dir);
+        match DirAccess::get_open_error() {
+            godot::global::Error::OK => {}
+            err => return Err(InternalError::FileNotReadable(dir.to_string(), err)),
+        }
+
+        let mut migrations = Vec::new();
+        for file in files.as_slice() {
+            let file = file.to_string();
+            let Some(stem) = file.strip_suffix(".sql") else {
+                continue;
+            };
+
+            let digits = stem.len() - stem.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let Ok(version) = stem[..digits].parse::<i64>() else {
+                return Err(InternalError::InvalidMigration(format!(
+                    "file name doesn't start with a version: {}",
+                    file
+                )));
+            };
+            let name = stem[digits..]
+                .trim_start_matches(['_', '-', ' '])
+                .to_string();
+
+            let path = format!("{}/{}", dir.to_string().trim_end_matches('/'), file);
+            let sql = FileAccess::get_file_as_string(&path);
+            match FileAccess::get_open_error() {
+                godot::global::Error::OK => migrations.push(Self::new(version, name, sql.into())),
+                err => return Err(InternalError::FileNotReadable(path, err)),
+            }
+        }
+        Ok(migrations)
+    }
+}
+
+/// Returns the SHA-256 of `sql` as a hex string.
+///
+/// Line endings are normalized first, so a script checked out with CRLF line
+/// endings keeps the checksum it was applied with.
+fn checksum(sql: &str) -> String {
+    let sql = sql.replace("\r\n", "\n");
+    let mut ctx = HashingContext::new_gd();
+    ctx.start(HashType::SHA256);
+    ctx.update(&PackedByteArray::from(sql.as_bytes()));
+    ctx.finish()
+        .as_slice()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
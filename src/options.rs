@@ -0,0 +1,249 @@
+use std::{io::Read, sync::RwLock, time::Duration};
+
+use godot::{
+    builtin::{VarDictionary, Variant, VariantType},
+    meta::FromGodot,
+};
+use rusqlite::{Connection, ErrorCode, MAIN_DB, OpenFlags};
+
+use crate::{
+    error::{Error, InternalError},
+    types::Value,
+};
+
+/// Options used to open a connection, parsed from the dictionary given to `open_with`.
+///
+/// They are kept by the worker alongside the connection, so every connection opened
+/// later for the same database (transactions, readers) is configured the same way.
+///
+/// # Keys
+///
+/// * `read_only` — open the database in read-only mode.
+/// * `create` — create the database if it doesn't exist (default `true`).
+/// * `uri` — interpret the path as a URI filename (default `true`).
+/// * `shared_cache` — enable SQLite's shared cache mode.
+/// * `busy_timeout` — milliseconds to wait for a locked database before failing.
+/// * `key` — the SQLCipher key of an encrypted database.
+/// * `rekey` — a new key to encrypt the database with once it is opened with `key`.
+/// * `journal_mode`, `synchronous`, `foreign_keys`, `cache_size` — set the pragma of the same name.
+/// * `pragmas` — a dictionary of any other pragmas to set, in order.
+#[derive(Debug, Default)]
+pub struct OpenOptions {
+    flags: OpenFlags,
+    busy_timeout: Option<Duration>,
+    // replaced by `rekey`, and by `AsletConn.rekey`, while the options are shared
+    key: RwLock<Option<String>>,
+    rekey: Option<String>,
+    pragmas: Vec<(String, Value)>,
+}
+
+impl OpenOptions {
+    pub fn is_read_only(&self) -> bool {
+        self.flags.contains(OpenFlags::SQLITE_OPEN_READ_ONLY)
+    }
+
+    pub fn has_pragma(&self, name: &str) -> bool {
+        self.pragmas
+            .iter()
+            .any(|(pragma, _)| pragma.eq_ignore_ascii_case(name))
+    }
+
+    pub fn key(&self) -> Option<String> {
+        self.key.read().unwrap().clone()
+    }
+
+    /// Replaces the key used by the connections opened from now on.
+    pub fn set_key(&self, key: String) {
+        *self.key.write().unwrap() = Some(key);
+    }
+
+    /// Changes the key of the database to `rekey`, if it was given.
+    ///
+    /// Only the first connection opened with the options rekeys the database; the ones
+    /// opened after it use the new key.
+    pub fn rekey(&mut self, conn: &Connection) -> Result<(), Error> {
+        if let Some(rekey) = self.rekey.take() {
+            conn.pragma_update(None, "rekey", &rekey)?;
+            self.set_key(rekey);
+        }
+        Ok(())
+    }
+
+    /// Opens a read-write connection to `path` and applies every option to it.
+    pub fn open(&self, path: &str) -> Result<Connection, Error> {
+        let conn = Connection::open_with_flags(path, self.flags)?;
+        self.unlock(&conn)?;
+        self.configure(&conn, |_| true, false)?;
+        Ok(conn)
+    }
+
+    /// Opens a read-only connection to `path`.
+    ///
+    /// `journal_mode` is skipped, since it is a property of the database file and is
+    /// already set by the read-write connection. For the same reason, the pragmas that
+    /// write to the file, such as `user_version`, are ignored when they fail because the
+    /// connection is read-only.
+    pub fn open_reader(&self, path: &str) -> Result<Connection, Error> {
+        let flags =
+            (self.flags - OpenFlags::SQLITE_OPEN_READ_WRITE - OpenFlags::SQLITE_OPEN_CREATE)
+                | OpenFlags::SQLITE_OPEN_READ_ONLY;
+        let conn = Connection::open_with_flags(path, flags)?;
+        self.unlock(&conn)?;
+        self.configure(
+            &conn,
+            |pragma| !pragma.eq_ignore_ascii_case("journal_mode"),
+            true,
+        )?;
+        Ok(conn)
+    }
+
+    /// Opens an in-memory connection holding a copy of the database in `bytes`.
+    ///
+    /// The copy is read-only when the options are, and `journal_mode` is skipped, since
+    /// an in-memory database always keeps its journal in memory. With a `key`, the bytes
+    /// are those of an encrypted database file, decrypted as they are read.
+    pub fn open_bytes(&self, bytes: &[u8]) -> Result<Connection, Error> {
+        // the header of a WAL database makes SQLite look for a WAL file, which an
+        // in-memory database can't have, so the copy is marked as a rollback database;
+        // encrypted databases have no plain header to check
+        let wal = bytes.starts_with(b"SQLite format 3\0") && bytes.get(18..20) == Some(&[2, 2]);
+        let reader: Box<dyn Read> = if wal {
+            Box::new(bytes[..18].chain(&[1, 1][..]).chain(&bytes[20..]))
+        } else {
+            Box::new(bytes)
+        };
+
+        let mut conn = Connection::open_in_memory()?;
+        conn.deserialize_read_exact(MAIN_DB, reader, bytes.len(), self.is_read_only())?;
+        self.unlock(&conn)?;
+        self.configure(
+            &conn,
+            |pragma| !pragma.eq_ignore_ascii_case("journal_mode"),
+            false,
+        )?;
+        Ok(conn)
+    }
+
+    fn unlock(&self, conn: &Connection) -> Result<(), Error> {
+        match self.key() {
+            Some(key) => apply_key(conn, &key),
+            None => Ok(()),
+        }
+    }
+
+    fn configure(
+        &self,
+        conn: &Connection,
+        filter: impl Fn(&str) -> bool,
+        reader: bool,
+    ) -> Result<(), rusqlite::Error> {
+        if let Some(timeout) = self.busy_timeout {
+            conn.busy_timeout(timeout)?;
+        }
+        for (pragma, value) in self.pragmas.iter().filter(|(p, _)| filter(p)) {
+            match conn.pragma_update(None, pragma, value) {
+                Err(err) if reader && err.sqlite_error_code() == Some(ErrorCode::ReadOnly) => {}
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<VarDictionary> for OpenOptions {
+    type Error = InternalError;
+
+    fn try_from(dict: VarDictionary) -> Result<Self, Self::Error> {
+        let mut options = OpenOptions::default();
+
+        for (key, value) in dict.iter_shared() {
+            let key = key.to_string();
+            match key.as_str() {
+                "read_only" => {
+                    let read_only = option::<bool>(&key, &value)?;
+                    options
+                        .flags
+                        .set(OpenFlags::SQLITE_OPEN_READ_ONLY, read_only);
+                    options
+                        .flags
+                        .set(OpenFlags::SQLITE_OPEN_READ_WRITE, !read_only);
+                    if read_only {
+                        options.flags.set(OpenFlags::SQLITE_OPEN_CREATE, false);
+                    }
+                }
+                "create" => {
+                    let create = option::<bool>(&key, &value)?;
+                    options.flags.set(OpenFlags::SQLITE_OPEN_CREATE, create);
+                }
+                "uri" => {
+                    let uri = option::<bool>(&key, &value)?;
+                    options.flags.set(OpenFlags::SQLITE_OPEN_URI, uri);
+                }
+                "shared_cache" => {
+                    let shared = option::<bool>(&key, &value)?;
+                    options
+                        .flags
+                        .set(OpenFlags::SQLITE_OPEN_SHARED_CACHE, shared);
+                    options
+                        .flags
+                        .set(OpenFlags::SQLITE_OPEN_PRIVATE_CACHE, !shared);
+                }
+                "key" | "rekey" if !cfg!(feature = "sqlcipher") => {
+                    return Err(InternalError::EncryptionUnsupported);
+                }
+                "key" => {
+                    *options.key.get_mut().unwrap() = Some(option::<String>(&key, &value)?);
+                }
+                "rekey" => options.rekey = Some(option::<String>(&key, &value)?),
+                "busy_timeout" => {
+                    let ms = option::<i64>(&key, &value)?.max(0) as u64;
+                    options.busy_timeout = Some(Duration::from_millis(ms));
+                }
+                "journal_mode" | "synchronous" | "foreign_keys" | "cache_size" => {
+                    options
+                        .pragmas
+                        .push((key.clone(), pragma_value(&key, value)?));
+                }
+                "pragmas" => {
+                    let pragmas = option::<VarDictionary>(&key, &value)?;
+                    for (name, value) in pragmas.iter_shared() {
+                        let name = name.to_string();
+                        let value = pragma_value(&name, value)?;
+                        options.pragmas.push((name, value));
+                    }
+                }
+                _ => return Err(InternalError::InvalidOption(key)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Sets the SQLCipher key of `conn`, which must happen before the database is read.
+///
+/// SQLCipher only finds out the key is wrong when it fails to decrypt the first page,
+/// so the schema is read right away to report it as [`InternalError::WrongKey`].
+pub fn apply_key(conn: &Connection, key: &str) -> Result<(), Error> {
+    conn.pragma_update(None, "key", key)?;
+    match conn.query_row("select count(*) from sqlite_schema", [], |_| Ok(())) {
+        Err(rusqlite::Error::SqliteFailure(err, _)) if err.code == ErrorCode::NotADatabase => {
+            Err(InternalError::WrongKey.into())
+        }
+        result => Ok(result?),
+    }
+}
+
+fn option<T: FromGodot>(key: &str, value: &Variant) -> Result<T, InternalError> {
+    value
+        .try_to::<T>()
+        .map_err(|_| InternalError::InvalidOption(key.to_string()))
+}
+
+fn pragma_value(name: &str, value: Variant) -> Result<Value, InternalError> {
+    match value.get_type() {
+        VariantType::BOOL => Ok(Value::Int(value.to::<bool>() as i64)),
+        VariantType::STRING_NAME => Ok(Value::String(value.to_string())),
+        _ => Value::try_from(value).map_err(|_| InternalError::InvalidOption(name.to_string())),
+    }
+}
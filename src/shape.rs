@@ -0,0 +1,175 @@
+use godot::prelude::*;
+
+use crate::{
+    codec::Decoder,
+    error::Error,
+    ok,
+    types::{Columns, Row, Rows, Value},
+};
+
+/// The form in which fetched rows are returned to GDScript.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Shape {
+    /// `[OK, rows, columns]`, each row an array of values.
+    #[default]
+    Rows,
+    /// `[OK, rows]`, each row a dictionary keyed by column name.
+    Dicts,
+    /// `[OK, row]` for exactly one row, as a dictionary.
+    One,
+    /// `[OK, row]` for at most one row, `row` being `null` if there is none.
+    Optional,
+    /// `[OK, value]`, the first column of the first row.
+    Value,
+    /// `[OK, values]`, every value of one column.
+    Column(usize),
+    /// `[OK, columns]`, a dictionary of column name to the values of that column.
+    Columns,
+}
+
+/// Builds the result of a fetch task in the requested shape.
+pub fn build(
+    rows: Rows,
+    columns: Columns,
+    shape: Shape,
+    decoder: Decoder,
+) -> Result<VarArray, Error> {
+    let rows = rows.as_ref();
+    Ok(match shape {
+        Shape::Rows => ok!(array_rows(rows, &decoder), columns),
+        Shape::Dicts => {
+            let mut result = VarArray::new();
+            for row in rows {
+                result.push(&dict(row, &columns, &decoder).to_variant());
+            }
+            ok!(result)
+        }
+        Shape::One => match rows {
+            [row] => ok!(dict(row, &columns, &decoder)),
+            [] => return Err(rusqlite::Error::QueryReturnedNoRows.into()),
+            _ => return Err(rusqlite::Error::QueryReturnedMoreThanOneRow.into()),
+        },
+        Shape::Optional => match rows {
+            [row] => ok!(dict(row, &columns, &decoder)),
+            [] => ok!(Variant::nil()),
+            _ => return Err(rusqlite::Error::QueryReturnedMoreThanOneRow.into()),
+        },
+        Shape::Value => match rows.first().and_then(|row| row.as_ref().first()) {
+            Some(value) => ok!(decoder.decode(0, value)),
+            None if columns.names().is_empty() => {
+                return Err(rusqlite::Error::InvalidColumnIndex(0).into());
+            }
+            None => return Err(rusqlite::Error::QueryReturnedNoRows.into()),
+        },
+        Shape::Column(index) => {
+            if index >= columns.names().len() {
+                return Err(rusqlite::Error::InvalidColumnIndex(index).into());
+            }
+            ok!(column(rows, index, &decoder))
+        }
+        Shape::Columns => {
+            let mut result = VarDictionary::new();
+            for (i, name) in columns.names().iter().enumerate() {
+                result.set(name.as_str(), column(rows, i, &decoder));
+            }
+            ok!(result)
+        }
+    })
+}
+
+/// Converts every row into an array of values, as returned by `fetch`.
+pub fn array_rows(rows: &[Row], decoder: &Decoder) -> VarArray {
+    let mut result = VarArray::new();
+    for row in rows {
+        result.push(&array(row, decoder).to_variant());
+    }
+    result
+}
+
+fn array(row: &Row, decoder: &Decoder) -> VarArray {
+    let mut values = VarArray::new();
+    for (i, value) in row.as_ref().iter().enumerate() {
+        values.push(&decoder.decode(i, value));
+    }
+    values
+}
+
+fn dict(row: &Row, columns: &Columns, decoder: &Decoder) -> VarDictionary {
+    let mut values = VarDictionary::new();
+    for (i, (name, value)) in columns.names().iter().zip(row.as_ref()).enumerate() {
+        values.set(name.as_str(), decoder.decode(i, value));
+    }
+    values
+}
+
+/// Collects one column into the narrowest container that holds all of its values.
+///
+/// Packed arrays are filled straight from the worker's values, skipping the
+/// Variant created for every value of a row-major result.
+///
+/// Integers become a `PackedInt64Array`, numbers a `PackedFloat64Array` (integers
+/// mixed with floats are widened), text a `PackedStringArray`, and blobs an array of
+/// `PackedByteArray`. Columns mixing other types, or holding `NULL`, become an array
+/// of Variants, as do columns decoded by their declared type.
+fn column(rows: &[Row], index: usize, decoder: &Decoder) -> Variant {
+    let values = || rows.iter().map(move |row| &row.as_ref()[index]);
+    if decoder.is_declared(index) {
+        return values()
+            .map(|value| decoder.decode(index, value))
+            .collect::<VarArray>()
+            .to_variant();
+    }
+
+    match kind(values()) {
+        Kind::Int => values()
+            .map(|value| match value {
+                Value::Int(v) => *v,
+                _ => unreachable!(),
+            })
+            .collect::<PackedInt64Array>()
+            .to_variant(),
+        Kind::Float => values()
+            .map(|value| match value {
+                Value::Int(v) => *v as f64,
+                Value::Number(v) => *v,
+                _ => unreachable!(),
+            })
+            .collect::<PackedFloat64Array>()
+            .to_variant(),
+        Kind::Text => values()
+            .map(|value| match value {
+                Value::String(v) => GString::from(v.as_str()),
+                _ => unreachable!(),
+            })
+            .collect::<PackedStringArray>()
+            .to_variant(),
+        Kind::Blob | Kind::Mixed => values()
+            .map(|value| value.to_variant())
+            .collect::<VarArray>()
+            .to_variant(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Float,
+    Text,
+    Blob,
+    Mixed,
+}
+
+fn kind<'a>(values: impl Iterator<Item = &'a Value>) -> Kind {
+    let mut kind = None;
+    for value in values {
+        kind = Some(match (kind, value) {
+            (None | Some(Kind::Int), Value::Int(_)) => Kind::Int,
+            (None | Some(Kind::Int | Kind::Float), Value::Number(_)) => Kind::Float,
+            (Some(Kind::Float), Value::Int(_)) => Kind::Float,
+            (None | Some(Kind::Text), Value::String(_)) => Kind::Text,
+            (None | Some(Kind::Blob), Value::Blob(_)) => Kind::Blob,
+            _ => return Kind::Mixed,
+        });
+    }
+    kind.unwrap_or(Kind::Mixed)
+}
@@ -1,57 +1,131 @@
 use godot::prelude::*;
+use rusqlite::InterruptHandle;
 use slab::Slab;
 use std::{
     cell::RefCell,
     rc::Rc,
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicU8, Ordering::SeqCst},
     },
+    time::{Duration, Instant},
 };
 
-use crate::api::{aslet::Aslet, task::AsletTask};
+use crate::{
+    api::{aslet::Aslet, task::AsletTask},
+    error::{Error, InternalError},
+    worker::Worker,
+};
 
 #[derive(Debug, Clone)]
-pub struct Tasks(Rc<RefCell<Slab<Gd<AsletTask>>>>);
+pub struct Tasks {
+    tasks: Rc<RefCell<Slab<Gd<AsletTask>>>>,
+    worker: Worker,
+}
 
 impl Tasks {
-    pub fn new() -> Self {
-        Self(Rc::new(RefCell::new(Slab::new())))
+    pub fn new(worker: Worker) -> Self {
+        Self {
+            tasks: Rc::new(RefCell::new(Slab::new())),
+            worker,
+        }
     }
 
     pub fn create(&self, aslet: Gd<Aslet>) -> (TaskContext, Gd<AsletTask>) {
-        let tasks = &mut *self.0.borrow_mut();
+        self.create_with_timeout(aslet, None)
+    }
+
+    /// Creates a task that fails with [`InternalError::Timeout`] if it hasn't
+    /// finished once `timeout` has elapsed, including while it is still queued.
+    pub fn create_with_timeout(
+        &self,
+        aslet: Gd<Aslet>,
+        timeout: Option<Duration>,
+    ) -> (TaskContext, Gd<AsletTask>) {
+        let tasks = &mut *self.tasks.borrow_mut();
         let entry = tasks.vacant_entry();
         let id = entry.key();
-        let task_ctx = TaskContext::new(id);
+        let task_ctx = TaskContext::new(id, timeout.map(|t| Instant::now() + t));
         let task = AsletTask::new(aslet, task_ctx.clone());
+        self.worker.watch(&task_ctx);
 
         entry.insert(task.clone());
         (task_ctx, task)
     }
 
     pub fn take(&self, key: usize) -> Option<Gd<AsletTask>> {
-        let tasks = &mut *self.0.borrow_mut();
+        let tasks = &mut *self.tasks.borrow_mut();
         tasks.try_remove(key)
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct TaskContext(usize, Arc<AtomicU8>);
+/// Resolves the `timeout_ms` argument of a task against the owner's default.
+///
+/// A negative value falls back to `default_ms`, and zero disables the timeout.
+pub fn timeout(timeout_ms: i64, default_ms: i64) -> Option<Duration> {
+    let ms = if timeout_ms < 0 {
+        default_ms
+    } else {
+        timeout_ms
+    };
+    (ms > 0).then(|| Duration::from_millis(ms as u64))
+}
+
+struct TaskState {
+    status: AtomicU8,
+    deadline: Option<Instant>,
+    interrupt: Mutex<Option<Arc<InterruptHandle>>>,
+}
+
+/// Shared state between an [`AsletTask`] and the worker executing it.
+///
+/// While the task is running, the context holds the [`InterruptHandle`] of the
+/// connection executing it, so that canceling from the main thread can abort the
+/// statement currently in progress. The same handle is used to interrupt the
+/// statement once the task's deadline, if any, has passed.
+#[derive(Clone)]
+pub struct TaskContext(usize, Arc<TaskState>);
 
 impl TaskContext {
     const WAITING: u8 = 0;
     const CANCELED: u8 = 1;
     const DONE: u8 = 2;
+    const RUNNING: u8 = 3;
+    const TIMED_OUT: u8 = 4;
+    /// Timed out before it started, and already reported as such.
+    const EXPIRED: u8 = 5;
 
-    pub fn new(id: usize) -> Self {
-        Self(id, Arc::new(AtomicU8::new(Self::WAITING)))
+    pub fn new(id: usize, deadline: Option<Instant>) -> Self {
+        Self(
+            id,
+            Arc::new(TaskState {
+                status: AtomicU8::new(Self::WAITING),
+                deadline,
+                interrupt: Mutex::new(None),
+            }),
+        )
     }
 
+    /// Cancels the task if it is waiting, or interrupts the statement it is running.
     pub fn cancel(&self) -> bool {
-        self.1
-            .compare_exchange(Self::WAITING, Self::CANCELED, SeqCst, SeqCst)
-            .is_ok()
+        self.abort(Self::CANCELED)
+    }
+
+    /// Fails the task with a timeout, interrupting its statement if it is running.
+    pub fn expire(&self) -> bool {
+        self.abort(Self::TIMED_OUT)
+    }
+
+    /// Fails the task with a timeout on behalf of the timer.
+    ///
+    /// Returns `true` if the task was still waiting, in which case the caller reports
+    /// the failure: the worker drops the task without replying once it gets to it.
+    pub fn time_out(&self) -> bool {
+        if self.transition(Self::WAITING, Self::EXPIRED) {
+            return true;
+        }
+        self.expire();
+        false
     }
 
     pub fn id(&self) -> usize {
@@ -59,10 +133,83 @@ impl TaskContext {
     }
 
     pub fn is_canceled(&self) -> bool {
-        self.1.load(SeqCst) == Self::CANCELED
+        self.1.status.load(SeqCst) == Self::CANCELED
+    }
+
+    /// Returns `true` if the task timed out while waiting, and was already reported.
+    pub fn is_expired(&self) -> bool {
+        self.1.status.load(SeqCst) == Self::EXPIRED
     }
 
-    pub fn done(&self) {
-        self.1.store(Self::DONE, SeqCst);
+    pub fn deadline(&self) -> Option<Instant> {
+        self.1.deadline
+    }
+
+    /// Returns `true` while the task is waiting or running.
+    pub fn is_pending(&self) -> bool {
+        matches!(self.1.status.load(SeqCst), Self::WAITING | Self::RUNNING)
+    }
+
+    /// Marks the task as running on the connection owning `interrupt`.
+    ///
+    /// Fails if the task was canceled or its deadline passed before it could start.
+    pub fn start(&self, interrupt: Option<Arc<InterruptHandle>>) -> Result<(), Error> {
+        if self.deadline().is_some_and(|d| d <= Instant::now()) {
+            self.expire();
+        }
+
+        let mut slot = self.1.interrupt.lock().unwrap();
+        if !self.transition(Self::WAITING, Self::RUNNING) {
+            return Err(self
+                .abort_error()
+                .unwrap_or(InternalError::Unreachable)
+                .into());
+        }
+        *slot = interrupt;
+        Ok(())
+    }
+
+    /// Marks the task as done and releases the interrupt handle.
+    ///
+    /// A task that failed after being canceled or timing out reports
+    /// [`InternalError::TaskCanceled`] or [`InternalError::Timeout`] instead of the
+    /// interruption error raised by SQLite.
+    pub fn finish<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        self.1.interrupt.lock().unwrap().take();
+        if self.transition(Self::RUNNING, Self::DONE) {
+            return result;
+        }
+        result.map_err(|e| self.abort_error().map_or(e, Error::from))
+    }
+
+    fn abort(&self, to: u8) -> bool {
+        if self.transition(Self::WAITING, to) {
+            return true;
+        }
+
+        let interrupt = self.1.interrupt.lock().unwrap();
+        if !self.transition(Self::RUNNING, to) {
+            return false;
+        }
+        if let Some(interrupt) = interrupt.as_ref() {
+            interrupt.interrupt();
+        }
+        true
+    }
+
+    fn abort_error(&self) -> Option<InternalError> {
+        match self.1.status.load(SeqCst) {
+            Self::CANCELED => Some(InternalError::TaskCanceled),
+            Self::TIMED_OUT | Self::EXPIRED => Some(InternalError::Timeout),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn transition(&self, from: u8, to: u8) -> bool {
+        self.1
+            .status
+            .compare_exchange(from, to, SeqCst, SeqCst)
+            .is_ok()
     }
 }
@@ -4,18 +4,44 @@ use godot::{
     prelude::*,
 };
 use rusqlite::{
-    ToSql,
+    Statement, ToSql,
     types::{ToSqlOutput, ValueRef},
 };
 
-use crate::error::InternalError;
+use crate::{codec, error::InternalError, shape::Shape};
 
-#[derive(Debug)]
-pub struct Columns(Vec<String>);
+/// Names and declared types of the columns returned by a statement.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    names: Vec<String>,
+    decltypes: Vec<Option<String>>,
+}
 
-impl From<Vec<String>> for Columns {
-    fn from(value: Vec<String>) -> Self {
-        Self(value)
+impl Columns {
+    pub fn of(stmt: &Statement) -> Self {
+        let (names, decltypes) = stmt
+            .columns()
+            .iter()
+            .map(|c| (c.name().to_string(), c.decl_type().map(str::to_string)))
+            .unzip();
+        Self { names, decltypes }
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the declared type of each column, `None` for expressions.
+    pub fn decltypes(&self) -> &[Option<String>] {
+        &self.decltypes
+    }
+
+    /// Sets the declared type of the column called `name`, so that values computed by an
+    /// expression can still be decoded.
+    pub fn declare(&mut self, name: &str, decltype: &str) {
+        if let Some(i) = self.names.iter().position(|n| n == name) {
+            self.decltypes[i] = Some(decltype.to_string());
+        }
     }
 }
 
@@ -28,8 +54,8 @@ impl ToGodot for Columns {
 
     fn to_godot(&self) -> PackedStringArray {
         let mut array = PackedStringArray::new();
-        array.resize(self.0.len());
-        for (i, value) in self.0.iter().enumerate() {
+        array.resize(self.names.len());
+        for (i, value) in self.names.iter().enumerate() {
             array[i] = value.to_godot();
         }
         array
@@ -88,7 +114,7 @@ impl<'a> From<ValueRef<'a>> for Value {
 }
 
 impl TryFrom<Variant> for Value {
-    type Error = InternalError;
+    type Error = VariantType;
 
     fn try_from(value: Variant) -> Result<Self, Self::Error> {
         match value.get_type() {
@@ -97,11 +123,32 @@ impl TryFrom<Variant> for Value {
             VariantType::STRING => Ok(Value::String(value.to())),
             VariantType::PACKED_BYTE_ARRAY => Ok(Value::Blob(value.to())),
             VariantType::NIL => Ok(Value::Null),
-            ty => Err(InternalError::UnsupportedVariantType(ty)),
+            VariantType::BOOL => Ok(Value::Int(value.to::<bool>() as i64)),
+            VariantType::STRING_NAME | VariantType::NODE_PATH => {
+                Ok(Value::String(value.to_string()))
+            }
+            ty => codec::encode(&value).ok_or(ty),
         }
     }
 }
 
+/// How fetched rows are converted before they are handed to GDScript.
+///
+/// Rows are converted on the main thread, so the format is sent along with the
+/// query and echoed back by the worker with its rows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Format {
+    /// Decode values according to the declared type of their column.
+    pub decode: bool,
+    pub shape: Shape,
+}
+
+impl Format {
+    pub fn with_shape(self, shape: Shape) -> Self {
+        Self { shape, ..self }
+    }
+}
+
 #[derive(Debug)]
 pub struct Row(Vec<Value>);
 
@@ -127,13 +174,22 @@ impl ToGodot for Row {
     }
 }
 
-impl From<Array<Variant>> for Row {
-    fn from(array: Array<Variant>) -> Self {
-        let values = array
+impl TryFrom<Array<Variant>> for Row {
+    type Error = InternalError;
+
+    /// Fails on the first value that can't be bound, reporting its 1-based index, as in
+    /// `?1`, so no parameter ends up in the wrong slot.
+    fn try_from(array: Array<Variant>) -> Result<Self, Self::Error> {
+        array
             .iter_shared()
-            .filter_map(|v| Value::try_from(v).ok())
-            .collect();
-        Self(values)
+            .enumerate()
+            .map(|(i, v)| {
+                Value::try_from(v).map_err(|ty| {
+                    InternalError::UnsupportedVariantType(format!("parameter {}", i + 1), ty)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
     }
 }
 
@@ -188,8 +244,108 @@ impl From<Vec<Row>> for Rows {
     }
 }
 
-impl From<Array<Array<Variant>>> for Rows {
-    fn from(rows: Array<Array<Variant>>) -> Self {
-        Self(rows.iter_shared().map(Row::from).collect())
+/// Parameters bound to a statement, either by position or by name.
+#[derive(Debug)]
+pub enum Params {
+    Positional(Row),
+    Named(Vec<(String, Value)>),
+}
+
+impl Params {
+    /// Converts every entry of `rows` into its own set of parameters.
+    ///
+    /// Errors report the 1-based index of the row in `rows` they were found in.
+    pub fn batch(rows: Array<Variant>) -> Result<Vec<Params>, InternalError> {
+        rows.iter_shared()
+            .enumerate()
+            .map(|(i, row)| match row.get_type() {
+                VariantType::ARRAY | VariantType::DICTIONARY => {
+                    Params::try_from(row).map_err(|err| match err {
+                        InternalError::UnsupportedVariantType(param, ty) => {
+                            InternalError::UnsupportedVariantType(
+                                format!("row {}, {}", i + 1, param),
+                                ty,
+                            )
+                        }
+                        err => err,
+                    })
+                }
+                ty => Err(InternalError::InvalidParams(format!("row {}", i + 1), ty)),
+            })
+            .collect()
+    }
+
+    /// Binds the parameters to `stmt`, replacing any previous bindings.
+    ///
+    /// Named parameters may be given with or without their `:`, `@` or `$` prefix.
+    /// Keys that don't match a parameter of the statement, and parameters left
+    /// without a key, fail with `InvalidParameterName`.
+    pub fn bind(&self, stmt: &mut Statement) -> rusqlite::Result<()> {
+        let expected = stmt.parameter_count();
+        match self {
+            Params::Positional(row) => {
+                if row.0.len() != expected {
+                    return Err(rusqlite::Error::InvalidParameterCount(
+                        row.0.len(),
+                        expected,
+                    ));
+                }
+                for (i, value) in row.0.iter().enumerate() {
+                    stmt.raw_bind_parameter(i + 1, value)?;
+                }
+            }
+            Params::Named(values) => {
+                let mut bound = vec![false; expected];
+                for (key, value) in values.iter() {
+                    let index = parameter_index(stmt, key)?
+                        .ok_or_else(|| rusqlite::Error::InvalidParameterName(key.clone()))?;
+                    stmt.raw_bind_parameter(index, value)?;
+                    bound[index - 1] = true;
+                }
+                if let Some(i) = bound.iter().position(|bound| !bound) {
+                    let name = stmt
+                        .parameter_name(i + 1)
+                        .map_or_else(|| format!("?{}", i + 1), str::to_string);
+                    return Err(rusqlite::Error::InvalidParameterName(name));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parameter_index(stmt: &Statement, key: &str) -> rusqlite::Result<Option<usize>> {
+    if key.starts_with([':', '@', '$', '?']) {
+        return stmt.parameter_index(key);
+    }
+    for prefix in [':', '@', '$'] {
+        if let Some(index) = stmt.parameter_index(&format!("{}{}", prefix, key))? {
+            return Ok(Some(index));
+        }
+    }
+    Ok(None)
+}
+
+impl TryFrom<Variant> for Params {
+    type Error = InternalError;
+
+    fn try_from(value: Variant) -> Result<Self, Self::Error> {
+        match value.get_type() {
+            VariantType::ARRAY => Ok(Params::Positional(Row::try_from(value.to::<VarArray>())?)),
+            VariantType::DICTIONARY => {
+                let dict = value.to::<VarDictionary>();
+                let mut values = Vec::with_capacity(dict.len());
+                for (key, value) in dict.iter_shared() {
+                    let key = key.to_string();
+                    let value = Value::try_from(value).map_err(|ty| {
+                        InternalError::UnsupportedVariantType(format!("parameter {:?}", key), ty)
+                    })?;
+                    values.push((key, value));
+                }
+                Ok(Params::Named(values))
+            }
+            VariantType::NIL => Ok(Params::Positional(Row(vec![]))),
+            ty => Err(InternalError::InvalidParams("parameters".to_string(), ty)),
+        }
     }
 }
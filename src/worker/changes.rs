@@ -0,0 +1,269 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use crossbeam::channel::Sender;
+use rusqlite::{
+    Connection,
+    hooks::{Action, AuthAction, AuthContext, Authorization},
+};
+
+use crate::{api::conn::AsletConn, error::InternalError, worker::messages::OutputMessage};
+
+/// How the rows changed through a connection are reported, set with `AsletConn.track_changes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeTracking {
+    Off,
+    /// Every changed row, each reported once per transaction and operation.
+    Rows,
+    /// One change per table and operation, so bulk writes send a handful of changes.
+    Tables,
+}
+
+impl TryFrom<i64> for ChangeTracking {
+    type Error = InternalError;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            AsletConn::CHANGES_OFF => Ok(ChangeTracking::Off),
+            AsletConn::CHANGES_ROWS => Ok(ChangeTracking::Rows),
+            AsletConn::CHANGES_TABLES => Ok(ChangeTracking::Tables),
+            _ => Err(InternalError::InvalidOption("mode".to_string())),
+        }
+    }
+}
+
+/// A row inserted, updated or deleted by a transaction.
+///
+/// `op` is one of `AsletConn.INSERT`, `AsletConn.UPDATE` or `AsletConn.DELETE`, and
+/// `rowid` is `-1` when changes are tracked by table.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RowChange {
+    pub op: i64,
+    pub table: String,
+    pub rowid: i64,
+}
+
+/// The end of a transaction on a connection whose changes are tracked.
+pub enum Changes {
+    Committed(Vec<RowChange>),
+    RolledBack,
+}
+
+/// The database a connection writes to, so that live queries reading from it can be
+/// found whichever connection wrote it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Database {
+    File(String),
+    /// An in-memory database, only reachable through the connection with the given id.
+    Memory(usize),
+}
+
+impl Database {
+    pub fn of(conn: &Connection, conn_id: usize) -> Self {
+        match conn.path() {
+            Some(path) if !path.is_empty() => Database::File(path.to_string()),
+            _ => Database::Memory(conn_id),
+        }
+    }
+}
+
+/// Tables written by a committed transaction, grouped by the database they belong to.
+pub type Modified = (Database, HashSet<String>);
+
+/// Changes made by the transaction in progress, in the order they were first made.
+struct Pending {
+    tracking: ChangeTracking,
+    changes: Vec<RowChange>,
+    seen: HashSet<RowChange>,
+    tables: HashSet<String>,
+    /// What was pending when each open savepoint started, outermost first.
+    savepoints: Vec<(usize, HashSet<String>)>,
+}
+
+impl Pending {
+    fn push(&mut self, change: RowChange) {
+        if !self.seen.contains(&change) {
+            self.seen.insert(change.clone());
+            self.changes.push(change);
+        }
+    }
+
+    fn take(&mut self) -> (Vec<RowChange>, HashSet<String>) {
+        self.seen.clear();
+        self.savepoints.clear();
+        (
+            std::mem::take(&mut self.changes),
+            std::mem::take(&mut self.tables),
+        )
+    }
+}
+
+/// Handle to the hooks of a connection, used to change how its changes are reported.
+#[derive(Clone)]
+pub struct Tracker(Arc<Mutex<Pending>>);
+
+impl Tracker {
+    pub fn tracking(&self) -> ChangeTracking {
+        self.0.lock().unwrap().tracking
+    }
+
+    /// Reports the changes of the transactions committed from now on with `tracking`.
+    pub fn set_tracking(&self, tracking: ChangeTracking) {
+        self.0.lock().unwrap().tracking = tracking;
+    }
+
+    /// Marks the start of a savepoint, whose changes are dropped when rolling back to it.
+    pub fn savepoint(&self) {
+        let mut pending = self.0.lock().unwrap();
+        let mark = (pending.changes.len(), pending.tables.clone());
+        pending.savepoints.push(mark);
+    }
+
+    /// Releases the savepoint at `depth`, keeping its changes in the enclosing one.
+    pub fn release(&self, depth: usize) {
+        self.0.lock().unwrap().savepoints.truncate(depth);
+    }
+
+    /// Drops the changes made since the savepoint at `depth` started, which stays open.
+    pub fn rollback_to(&self, depth: usize) {
+        let mut pending = self.0.lock().unwrap();
+        let Some((len, tables)) = pending.savepoints.get(depth).cloned() else {
+            return;
+        };
+        pending.savepoints.truncate(depth + 1);
+        pending.tables = tables;
+        let dropped = pending.changes.split_off(len);
+        for change in dropped {
+            pending.seen.remove(&change);
+        }
+    }
+}
+
+/// Registers the hooks of every connection the worker writes through.
+///
+/// The tables written by each committed transaction are always collected, to re-run
+/// the live queries reading them, while its rows are only sent to the main thread when
+/// the connection tracks its changes.
+pub struct Hooks {
+    sender: Sender<OutputMessage>,
+    /// Id of the writer thread, sent along with the changes since every writer numbers
+    /// its connections from `0`.
+    writer_id: usize,
+    modified: Arc<Mutex<Vec<Modified>>>,
+}
+
+impl Hooks {
+    pub fn new(sender: Sender<OutputMessage>, writer_id: usize) -> Self {
+        Self {
+            sender,
+            writer_id,
+            modified: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Takes the tables modified by the transactions committed since the last call.
+    pub fn take_modified(&self) -> Vec<Modified> {
+        std::mem::take(&mut self.modified.lock().unwrap())
+    }
+
+    /// Registers the update, commit and rollback hooks of `conn`, reporting its changes as
+    /// the ones of the connection `conn_id`.
+    ///
+    /// Changes are collected as rows are written and sent to the main thread in a single
+    /// message once the transaction commits, or dropped if it rolls back, so only committed
+    /// changes are ever reported. Statements run outside a transaction commit on their own.
+    pub fn install(&self, conn: &Connection, conn_id: usize, tracking: ChangeTracking) -> Tracker {
+        // the hooks only ever run on the worker thread, but rusqlite requires them to be `Send`
+        let pending = Arc::new(Mutex::new(Pending {
+            tracking,
+            changes: Vec::new(),
+            seen: HashSet::new(),
+            tables: HashSet::new(),
+            savepoints: Vec::new(),
+        }));
+
+        let written = pending.clone();
+        conn.update_hook(Some(move |action, _: &str, table: &str, rowid| {
+            let op = match action {
+                Action::SQLITE_INSERT => AsletConn::INSERT,
+                Action::SQLITE_UPDATE => AsletConn::UPDATE,
+                Action::SQLITE_DELETE => AsletConn::DELETE,
+                _ => return,
+            };
+            let mut pending = written.lock().unwrap();
+            if !pending.tables.contains(table) {
+                pending.tables.insert(table.to_string());
+            }
+            let rowid = match pending.tracking {
+                ChangeTracking::Off => return,
+                ChangeTracking::Rows => rowid,
+                ChangeTracking::Tables => -1,
+            };
+            pending.push(RowChange {
+                op,
+                table: table.to_string(),
+                rowid,
+            });
+        }));
+
+        let (committed, output, modified) =
+            (pending.clone(), self.sender.clone(), self.modified.clone());
+        let database = Database::of(conn, conn_id);
+        let writer_id = self.writer_id;
+        conn.commit_hook(Some(move || {
+            let mut pending = committed.lock().unwrap();
+            let (changes, tables) = pending.take();
+            if !tables.is_empty() {
+                modified.lock().unwrap().push((database.clone(), tables));
+            }
+            if pending.tracking != ChangeTracking::Off {
+                // a closed channel means the instance is gone, along with anyone to notify
+                let changes = Changes::Committed(changes);
+                let _ = output.send(OutputMessage::Changes(writer_id, conn_id, changes));
+            }
+            false
+        }));
+
+        let (rolled_back, output) = (pending.clone(), self.sender.clone());
+        conn.rollback_hook(Some(move || {
+            let mut pending = rolled_back.lock().unwrap();
+            pending.take();
+            if pending.tracking != ChangeTracking::Off {
+                let changes = Changes::RolledBack;
+                let _ = output.send(OutputMessage::Changes(writer_id, conn_id, changes));
+            }
+        }));
+
+        authorize_writes(conn);
+        Tracker(pending)
+    }
+}
+
+/// Finds the tables `sql` reads from, including the ones read through its views, by
+/// preparing it with an authorizer that records them.
+pub fn read_tables(conn: &Connection, sql: &str) -> Result<HashSet<String>, rusqlite::Error> {
+    let tables = Arc::new(Mutex::new(HashSet::new()));
+    let read = tables.clone();
+    conn.authorizer(Some(move |ctx: AuthContext<'_>| {
+        if let AuthAction::Read { table_name, .. } = ctx.action {
+            read.lock().unwrap().insert(table_name.to_string());
+        }
+        Authorization::Allow
+    }));
+    let result = conn.prepare(sql).map(drop);
+    authorize_writes(conn);
+
+    result?;
+    Ok(std::mem::take(&mut tables.lock().unwrap()))
+}
+
+/// Allows every statement, but keeps SQLite from truncating a table on an unqualified
+/// `DELETE`, which would otherwise skip the update hook for its rows.
+fn authorize_writes(conn: &Connection) {
+    conn.authorizer(Some(|ctx: AuthContext<'_>| match ctx.action {
+        AuthAction::Delete { .. } => Authorization::Ignore,
+        _ => Authorization::Allow,
+    }));
+}
@@ -0,0 +1,94 @@
+use std::rc::Rc;
+
+use rusqlite::{Connection, Statement};
+
+use crate::{
+    error::Error,
+    types::{Columns, Params, Row, Rows},
+    worker::pool::{Interrupt, OpenCursor},
+};
+
+/// A query whose rows are read in batches, keeping its statement alive on the worker
+/// between them.
+///
+/// # Safety
+///
+/// `rusqlite::Rows` borrows the `Statement` mutably, which in turn borrows the
+/// `Connection`. To keep all three together, the borrows are extended to `'static`
+/// with `std::mem::transmute`. This is sound because:
+///
+/// * The `Connection` is kept alive by the `Rc` held in `_conn`, even if the connection
+///   is closed by the main thread in the meantime.
+/// * The `Statement` is boxed, so its address doesn't change when the `Cursor` moves.
+/// * Fields are dropped in declaration order: `rows` resets the statement before
+///   `_stmt` finalizes it, and both happen before `_conn` releases the connection.
+/// * A `Cursor` never leaves the worker thread, which owns the connection.
+pub struct Cursor {
+    rows: rusqlite::Rows<'static>,
+    _stmt: Box<Statement<'static>>,
+    _conn: Rc<Connection>,
+    // keeps the connection from being interrupted while the statement is running
+    _open: OpenCursor,
+    columns: Columns,
+    // set once reading a row fails, after which the statement yields no more rows
+    failed: bool,
+}
+
+impl Cursor {
+    pub fn new(
+        conn: Rc<Connection>,
+        interrupt: &Interrupt,
+        query: &str,
+        params: Params,
+    ) -> Result<Self, Error> {
+        let mut stmt = unsafe {
+            let conn_ref: *const Connection = &*conn;
+            let stmt = (*conn_ref).prepare(query)?;
+            Box::new(std::mem::transmute::<Statement<'_>, Statement<'static>>(
+                stmt,
+            ))
+        };
+        let columns = Columns::of(&stmt);
+        params.bind(&mut stmt)?;
+
+        let rows = unsafe {
+            let stmt_ref: *mut Statement<'static> = &mut *stmt;
+            let rows = (*stmt_ref).raw_query();
+            std::mem::transmute::<rusqlite::Rows<'_>, rusqlite::Rows<'static>>(rows)
+        };
+
+        Ok(Self {
+            rows,
+            _stmt: stmt,
+            _conn: conn,
+            _open: interrupt.open_cursor(),
+            columns,
+            failed: false,
+        })
+    }
+
+    pub fn columns(&self) -> Columns {
+        self.columns.clone()
+    }
+
+    /// Returns `true` once a batch failed, after which the cursor can't be read anymore.
+    pub fn has_failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Reads up to `count` rows. Fewer rows are returned once the query is exhausted.
+    pub fn next_batch(&mut self, count: usize) -> Result<(Rows, Columns), Error> {
+        let mut batch = Vec::with_capacity(count.min(1024));
+        while batch.len() < count {
+            match self.rows.next() {
+                Ok(Some(row)) => batch.push(Row::from(row)),
+                Ok(None) => break,
+                Err(err) => {
+                    self.failed = true;
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok((batch.into(), self.columns()))
+    }
+}
@@ -1,221 +1,938 @@
-use crossbeam::channel::{Receiver, Sender};
-use godot::{classes::ProjectSettings, global::printerr, meta::ToGodot, obj::Singleton};
-use rusqlite::{Connection, ToSql};
+use std::{collections::VecDeque, path::Path, sync::Arc, time::Instant};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use godot::{
+    classes::{FileAccess, ProjectSettings, file_access::ModeFlags},
+    global::printerr,
+    meta::ToGodot,
+    obj::Singleton,
+};
+use rusqlite::{
+    Batch, Connection, MAIN_DB, OptionalExtension, Statement, Transaction, TransactionBehavior,
+    fallible_iterator::FallibleIterator,
+};
 use slab::Slab;
 
 use crate::{
-    api::transaction::TransactionState,
-    backup::BackupState,
+    api::transaction::{TransactionMode, TransactionState},
+    backup::{BackupProgress, BackupState},
     error::{Error, InternalError},
-    types::{Columns, Row, Rows},
-    worker::messages::{InputMessage, OutputMessage},
+    migration::Migration,
+    options::OpenOptions,
+    tasks::TaskContext,
+    types::{Columns, Format, Params, Row, Rows},
+    worker::{
+        changes::{ChangeTracking, Database, Hooks},
+        cursor::Cursor,
+        live::{LiveQueries, WatchRequest},
+        messages::{InputMessage, OutputMessage, ReadMessage},
+        pool::{Pool, ReaderSet},
+        statement::PreparedStatement,
+    },
 };
 
-pub fn message_loop(input_receiver: Receiver<InputMessage>, output_sender: Sender<OutputMessage>) {
-    let mut conn_pool: Slab<Connection> = Slab::new();
+pub fn message_loop(
+    input_receiver: Receiver<InputMessage>,
+    output_sender: Sender<OutputMessage>,
+    with_readers: bool,
+    writer_id: usize,
+) {
+    let mut conn_pool = Pool::new();
+    let mut backlog: VecDeque<InputMessage> = VecDeque::new();
+    let mut cursors: Slab<Cursor> = Slab::new();
+    let mut statements: Slab<PreparedStatement> = Slab::new();
+    let mut backups: Slab<BackupState> = Slab::new();
+    let mut live = LiveQueries::new();
+    let hooks = Hooks::new(output_sender.clone(), writer_id);
+
+    macro_rules! reply {
+        ($output:expr) => {{
+            if output_sender.send($output).is_err() {
+                printerr(&["aslet instance was dropped prematurely".to_variant()]);
+            }
+        }};
+    }
 
     macro_rules! handle {
-        ($task_ctx:ident, $task:expr, $output:expr) => {{
-            if $task_ctx.is_canceled() {
-                let _ = output_sender.send(OutputMessage::Canceled($task_ctx));
+        ($task_ctx:ident, $interrupt:expr, $task:expr, $output:expr) => {{
+            if let Err(err) = $task_ctx.start($interrupt) {
+                // a task that timed out while queued was already failed by the timer
+                if !$task_ctx.is_expired() {
+                    reply!($output($task_ctx, Err(err)));
+                }
                 continue;
             }
 
-            let result = $task.map_err(Error::from);
-            $task_ctx.done();
-            if let Err(_) = output_sender.send($output($task_ctx, result)) {
-                printerr(&["aslet instance was dropped prematurely".to_variant()]);
-            }
+            let result = $task_ctx.finish($task.map_err(Error::from));
+            reply!($output($task_ctx, result));
         }};
     }
 
-    for msg in input_receiver {
+    loop {
+        // live queries reading the tables written so far run once their debounce elapses
+        live.mark(hooks.take_modified(), Instant::now());
+        backlog.extend(live.take_due(Instant::now()));
+
+        let msg = match backlog.pop_front() {
+            Some(msg) => msg,
+            None => match live.next_due() {
+                Some(due) => match input_receiver.recv_deadline(due) {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match input_receiver.recv() {
+                    Ok(msg) => msg,
+                    Err(_) => break,
+                },
+            },
+        };
+
+        // work for a connection lent to a transaction waits until the transaction ends
+        let msg = match msg.conn_id() {
+            Some(conn_id) => match conn_pool.defer(conn_id, msg) {
+                Some(msg) => msg,
+                None => continue,
+            },
+            None => msg,
+        };
+
         match msg {
-            InputMessage::Open(task_ctx, path) => {
-                handle!(task_ctx, open(&mut conn_pool, path), OutputMessage::Open);
+            InputMessage::Open(task_ctx, path, options) => {
+                handle!(
+                    task_ctx,
+                    None,
+                    open(&mut conn_pool, &hooks, path, options, with_readers),
+                    OutputMessage::Open
+                );
+            }
+            InputMessage::OpenBytes(task_ctx, bytes, options) => {
+                handle!(
+                    task_ctx,
+                    None,
+                    open_bytes(&mut conn_pool, &hooks, &bytes, Arc::new(options)),
+                    OutputMessage::Open
+                );
             }
-            InputMessage::BeginTransaction(ctx, path) => {
+            InputMessage::BeginTransaction(ctx, conn_id, mode, borrow, timeout_ms, format) => {
+                // a dedicated connection is opened first, so that canceling the task
+                // interrupts the BEGIN waiting for its lock
+                let dedicated = open_dedicated(&conn_pool, conn_id, borrow);
+                let interrupt = match &dedicated {
+                    Ok(Some(conn)) => Some(Arc::new(conn.get_interrupt_handle())),
+                    _ => conn_pool.interrupt(conn_id),
+                };
                 handle!(
                     ctx,
-                    begin_transaction(&mut conn_pool, path),
+                    interrupt,
+                    dedicated
+                        .and_then(|dedicated| {
+                            begin_transaction(&mut conn_pool, &hooks, conn_id, mode, dedicated)
+                        })
+                        .map(|id| (id, timeout_ms, format)),
                     OutputMessage::TransactionStarted
                 );
             }
             InputMessage::Rollback(ctx, conn_id, state) => {
+                let was_active = state.is_active();
                 handle!(
                     ctx,
-                    rollback(&mut conn_pool, conn_id, state),
+                    conn_pool.interrupt(conn_id),
+                    rollback(&mut conn_pool, conn_id, state.clone()),
                     OutputMessage::TransactionRolledBack
                 );
+                if was_active && !state.is_active() {
+                    resume(&mut backlog, conn_pool.end_transaction(conn_id));
+                }
             }
             InputMessage::Commit(ctx, conn_id, state) => {
+                let was_active = state.is_active();
                 handle!(
                     ctx,
-                    commit(&mut conn_pool, conn_id, state),
+                    conn_pool.interrupt(conn_id),
+                    commit(&mut conn_pool, conn_id, state.clone()),
                     OutputMessage::TransactionCommitted
                 );
+                if was_active && !state.is_active() {
+                    resume(&mut backlog, conn_pool.end_transaction(conn_id));
+                }
             }
-            InputMessage::BatchInsert(conn_id, ctx, query, rows) => {
+            InputMessage::Savepoint(ctx, conn_id, parent, name, timeout_ms, format) => {
                 handle!(
                     ctx,
-                    batch_insert(&mut conn_pool, conn_id, rows, query),
-                    OutputMessage::Exec
+                    conn_pool.interrupt(conn_id),
+                    savepoint(&mut conn_pool, conn_id, parent)
+                        .map(|state| (conn_id, name, state, timeout_ms, format)),
+                    OutputMessage::SavepointStarted
+                );
+            }
+            InputMessage::Release(ctx, conn_id, state) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    release(&mut conn_pool, conn_id, state),
+                    OutputMessage::SavepointReleased
+                );
+            }
+            InputMessage::RollbackTo(ctx, conn_id, state) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    rollback_to(&mut conn_pool, conn_id, state),
+                    OutputMessage::SavepointRolledBack
+                );
+            }
+            InputMessage::BatchInsert(conn_id, ctx, query, rows, return_rowids) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    batch_insert(&conn_pool, conn_id, rows, query, return_rowids),
+                    OutputMessage::BatchInserted
                 );
             }
             InputMessage::Exec(conn_id, ctx, query, params) => {
                 handle!(
                     ctx,
+                    conn_pool.interrupt(conn_id),
                     exec(&conn_pool, conn_id, params, query),
                     OutputMessage::Exec
                 );
             }
-            InputMessage::Fetch(conn_id, ctx, query, params) => {
+            InputMessage::ExecScript(conn_id, ctx, sql, transaction) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    exec_script(&conn_pool, conn_id, &sql, transaction),
+                    OutputMessage::Script
+                );
+            }
+            InputMessage::Migrate(conn_id, ctx, migrations) => {
                 handle!(
                     ctx,
-                    fetch(&conn_pool, conn_id, params, query),
+                    conn_pool.interrupt(conn_id),
+                    conn_pool
+                        .get(conn_id)
+                        .and_then(|conn| migrate(conn, migrations)),
+                    OutputMessage::Migrated
+                );
+            }
+            InputMessage::Schema(conn_id, ctx, schema, format) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    conn_pool
+                        .get(conn_id)
+                        .and_then(|conn| schema.fetch(conn))
+                        .map(|(rows, columns)| (rows, columns, format)),
                     OutputMessage::Fetch
                 );
             }
-            InputMessage::BeginBackup(ctx, request) => {
+            InputMessage::Serialize(conn_id, ctx) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    serialize(&conn_pool, conn_id),
+                    OutputMessage::Serialized
+                );
+            }
+            InputMessage::TrackChanges(conn_id, ctx, tracking) => {
                 handle!(
                     ctx,
-                    BackupState::new(request).map_err(Error::from),
-                    OutputMessage::Backup
+                    None,
+                    conn_pool
+                        .tracker(conn_id)
+                        .map(|tracker| tracker.set_tracking(tracking)),
+                    OutputMessage::ChangesTracked
                 );
             }
-            InputMessage::BackupStep(ctx, mut backup) => {
+            InputMessage::Rekey(conn_id, ctx, key) => {
                 handle!(
                     ctx,
-                    backup.worker().step().map(|_| backup).map_err(Error::from),
-                    OutputMessage::Backup
+                    conn_pool.interrupt(conn_id),
+                    rekey(&conn_pool, conn_id, key),
+                    OutputMessage::Rekeyed
                 );
             }
+            InputMessage::Fetch(conn_id, ctx, query, params, format) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    conn_pool
+                        .get(conn_id)
+                        .and_then(|conn| fetch(conn, params, query))
+                        .map(|(rows, columns)| (rows, columns, format)),
+                    OutputMessage::Fetch
+                );
+            }
+            InputMessage::OpenCursor(conn_id, ctx, query, params, timeout_ms, format) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    open_cursor(&conn_pool, &mut cursors, conn_id, params, query).map(
+                        |(cursor_id, columns)| (conn_id, cursor_id, columns, timeout_ms, format)
+                    ),
+                    OutputMessage::CursorOpened
+                );
+            }
+            InputMessage::CursorNext(_, ctx, cursor_id, count, format) => {
+                // a connection isn't interrupted while a cursor is open on it, see `Interrupt`
+                handle!(
+                    ctx,
+                    None,
+                    cursors
+                        .get_mut(cursor_id)
+                        .filter(|cursor| !cursor.has_failed())
+                        .ok_or(Error::from(InternalError::InvalidCursor(cursor_id)))
+                        .and_then(|cursor| cursor.next_batch(count))
+                        .map(|(rows, columns)| (rows, columns, format)),
+                    OutputMessage::Fetch
+                );
+            }
+            InputMessage::Prepare(conn_id, ctx, query, timeout_ms, format) => {
+                handle!(
+                    ctx,
+                    conn_pool.interrupt(conn_id),
+                    prepare(&conn_pool, &mut statements, conn_id, query).map(
+                        |(stmt_id, columns, parameter_count)| {
+                            (
+                                conn_id,
+                                stmt_id,
+                                columns,
+                                parameter_count,
+                                timeout_ms,
+                                format,
+                            )
+                        }
+                    ),
+                    OutputMessage::Prepared
+                );
+            }
+            InputMessage::ExecStatement(_, ctx, stmt_id, params) => {
+                handle!(
+                    ctx,
+                    statements
+                        .get(stmt_id)
+                        .and_then(PreparedStatement::interrupt),
+                    get_statement(&mut statements, stmt_id).and_then(|stmt| {
+                        let (conn, stmt) = stmt.parts_mut();
+                        execute(conn, stmt, params)
+                    }),
+                    OutputMessage::Exec
+                );
+            }
+            InputMessage::FetchStatement(_, ctx, stmt_id, params, format) => {
+                handle!(
+                    ctx,
+                    statements
+                        .get(stmt_id)
+                        .and_then(PreparedStatement::interrupt),
+                    get_statement(&mut statements, stmt_id)
+                        .and_then(|stmt| query(stmt.get_mut(), &params))
+                        .map(|(rows, columns)| (rows, columns, format)),
+                    OutputMessage::Fetch
+                );
+            }
+            InputMessage::BeginBackup(ctx, request) => {
+                // the task keeps running between steps so that it can still be canceled,
+                // which is checked before each step rather than by interrupting
+                if let Err(err) = ctx.start(None) {
+                    if !ctx.is_expired() {
+                        reply!(OutputMessage::Backup(ctx, Err(err)));
+                    }
+                    continue;
+                }
+
+                // work for a connection being restored waits until the restore ends
+                let restoring = request.restore.then_some(request.conn_id);
+                let result = match restoring {
+                    Some(conn_id) => conn_pool.begin_restore(conn_id),
+                    None => Ok(()),
+                }
+                .and_then(|_| conn_pool.share(request.conn_id))
+                .and_then(|(conn, _)| {
+                    let (_, options) = conn_pool.source(request.conn_id)?;
+                    BackupState::new(conn, request, options.key())
+                })
+                .map(|backup| backup_progress(&mut backups, backup, false));
+                if let (Some(conn_id), Err(_)) = (restoring, &result) {
+                    resume(&mut backlog, conn_pool.end_restore(conn_id));
+                }
+                let result = settle_backup(&ctx, result);
+                reply!(OutputMessage::Backup(ctx, result));
+            }
+            InputMessage::BackupStep(ctx, backup_id) => {
+                let Some(mut backup) = backups.try_remove(backup_id) else {
+                    reply!(OutputMessage::Failed(
+                        ctx,
+                        InternalError::Unreachable.into()
+                    ));
+                    continue;
+                };
+                let restoring = backup.restoring();
+                if ctx.is_canceled() {
+                    backup.discard();
+                    if let Some(conn_id) = restoring {
+                        resume(&mut backlog, conn_pool.end_restore(conn_id));
+                    }
+                    reply!(OutputMessage::Canceled(ctx));
+                    continue;
+                }
+
+                let result = match backup.step() {
+                    Ok(done) => {
+                        if let Some(conn_id) = restoring.filter(|_| done) {
+                            resume(&mut backlog, conn_pool.end_restore(conn_id));
+                            // a restore replaces every table at once, bypassing the hooks
+                            if let Ok(conn) = conn_pool.get(conn_id) {
+                                live.mark_all(&Database::of(conn, conn_id), Instant::now());
+                            }
+                        }
+                        Ok(backup_progress(&mut backups, backup, done))
+                    }
+                    Err(err) => {
+                        backup.discard();
+                        if let Some(conn_id) = restoring {
+                            resume(&mut backlog, conn_pool.end_restore(conn_id));
+                        }
+                        Err(err.into())
+                    }
+                };
+                let result = settle_backup(&ctx, result);
+                reply!(OutputMessage::Backup(ctx, result));
+            }
+            InputMessage::Watch(request) => {
+                let live_id = request.live_id;
+                let result = conn_pool.get(request.conn_id).and_then(|conn| {
+                    live.watch(conn, request)
+                        .and_then(|request| run_live(conn, request))
+                });
+                reply!(OutputMessage::LiveChanged(live_id, result));
+            }
+            InputMessage::RunLive(conn_id, live_id) => {
+                // the query may have stopped being watched since its run was queued
+                let (Some(request), Ok(conn)) = (live.start(live_id), conn_pool.get(conn_id))
+                else {
+                    continue;
+                };
+                reply!(OutputMessage::LiveChanged(live_id, run_live(conn, request)));
+            }
             InputMessage::CloseConn(conn_id) => {
-                if let None = conn_pool.try_remove(conn_id) {
+                if conn_pool.remove(conn_id).is_none() {
                     let err_msg = format!("can't close connection, invalid id {}", conn_id);
                     printerr(&[err_msg.to_variant()]);
                 }
             }
-            InputMessage::Quit => break,
+            InputMessage::CloseCursor(_, cursor_id) => {
+                cursors.try_remove(cursor_id);
+            }
+            InputMessage::CloseStatement(stmt_id) => {
+                statements.try_remove(stmt_id);
+            }
+            InputMessage::Unwatch(live_id) => live.unwatch(live_id),
         }
     }
 }
 
-fn get_conn(conn_pool: &Slab<Connection>, conn_id: usize) -> Result<&Connection, Error> {
-    conn_pool
-        .get(conn_id)
-        .ok_or_else(|| InternalError::InvalidConnection(conn_id).into())
-}
+pub fn reader_loop(read_receiver: Receiver<ReadMessage>, output_sender: Sender<OutputMessage>) {
+    for msg in read_receiver {
+        match msg {
+            ReadMessage::Fetch(readers, ctx, query, params, format) => {
+                let pooled = readers.acquire();
+                let interrupt = pooled.as_ref().ok().map(|pooled| pooled.interrupt.clone());
+                if let Err(err) = ctx.start(interrupt) {
+                    if let Ok(pooled) = pooled {
+                        readers.release(pooled);
+                    }
+                    // a task that timed out while queued was already failed by the timer
+                    if !ctx.is_expired() {
+                        let _ = output_sender.send(OutputMessage::Fetch(ctx, Err(err)));
+                    }
+                    continue;
+                }
 
-fn get_conn_mut(
-    conn_pool: &mut Slab<Connection>,
-    conn_id: usize,
-) -> Result<&mut Connection, Error> {
-    conn_pool
-        .get_mut(conn_id)
-        .ok_or_else(|| InternalError::InvalidConnection(conn_id).into())
+                let result = ctx
+                    .finish(pooled.and_then(|pooled| {
+                        let result = fetch(&pooled.conn, params, query);
+                        readers.release(pooled);
+                        result
+                    }))
+                    .map(|(rows, columns)| (rows, columns, format));
+
+                if output_sender
+                    .send(OutputMessage::Fetch(ctx, result))
+                    .is_err()
+                {
+                    printerr(&["aslet instance was dropped prematurely".to_variant()]);
+                }
+            }
+        }
+    }
 }
 
-fn open(conn_pool: &mut Slab<Connection>, path: String) -> Result<(usize, String), Error> {
+fn open(
+    conn_pool: &mut Pool,
+    hooks: &Hooks,
+    path: String,
+    mut options: OpenOptions,
+    with_readers: bool,
+) -> Result<(usize, Option<Arc<ReaderSet>>), Error> {
     let real_path = ProjectSettings::singleton()
         .globalize_path(&path)
         .to_string();
-    let conn = Connection::open(real_path)?;
-    let conn_id = conn_pool.insert(conn);
-    Ok((conn_id, path))
+
+    // exported projects pack `res://` into the PCK, where SQLite can't open it, so the
+    // database is read through Godot and loaded into memory instead
+    if path.starts_with("res://") && !Path::new(&real_path).exists() {
+        // `FileAccess::get_open_error` is shared with the main thread, so only the error
+        // of this file's own handle is checked
+        let Some(file) = FileAccess::open(&path, ModeFlags::READ) else {
+            let err = godot::global::Error::ERR_FILE_CANT_OPEN;
+            return Err(InternalError::FileNotReadable(path, err).into());
+        };
+        let bytes = file.get_buffer(file.get_length() as i64);
+        match file.get_error() {
+            godot::global::Error::OK => {}
+            err => return Err(InternalError::FileNotReadable(path, err).into()),
+        }
+        return open_bytes(conn_pool, hooks, bytes.as_slice(), Arc::new(options));
+    }
+
+    let conn = options.open(&real_path)?;
+    options.rekey(&conn)?;
+    let options = Arc::new(options);
+
+    // readers only make sense for databases other connections can see, and
+    // need WAL so they never block, nor are blocked by, the writer
+    let readers = match conn.path() {
+        Some(file) if with_readers && !file.is_empty() && !options.is_read_only() => {
+            if !options.has_pragma("journal_mode") {
+                conn.pragma_update(None, "journal_mode", "WAL")?;
+            }
+            let mode: String = conn.pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+            mode.eq_ignore_ascii_case("wal")
+                .then(|| Arc::new(ReaderSet::new(real_path.clone(), options.clone())))
+        }
+        _ => None,
+    };
+
+    let tracker = hooks.install(&conn, conn_pool.next_id(), ChangeTracking::Off);
+    let conn_id = conn_pool.insert(conn, real_path, options, tracker);
+    Ok((conn_id, readers))
 }
 
-fn begin_transaction(conn_pool: &mut Slab<Connection>, path: String) -> Result<usize, Error> {
-    let real_path = ProjectSettings::singleton()
-        .globalize_path(&path)
-        .to_string();
+/// Opens an in-memory database from a serialized copy.
+///
+/// The connection has no file other connections could open, so it never gets readers
+/// and its transactions always borrow it.
+fn open_bytes(
+    conn_pool: &mut Pool,
+    hooks: &Hooks,
+    bytes: &[u8],
+    options: Arc<OpenOptions>,
+) -> Result<(usize, Option<Arc<ReaderSet>>), Error> {
+    let conn = options.open_bytes(bytes)?;
+    let tracker = hooks.install(&conn, conn_pool.next_id(), ChangeTracking::Off);
+    Ok((
+        conn_pool.insert(conn, String::new(), options, tracker),
+        None,
+    ))
+}
 
-    let conn = Connection::open(real_path)?;
-    conn.execute("BEGIN TRANSACTION;", [])?;
-    let conn_id = conn_pool.insert(conn);
+/// Queues the messages deferred by a transaction ahead of everything else.
+fn resume(backlog: &mut VecDeque<InputMessage>, deferred: VecDeque<InputMessage>) {
+    for msg in deferred.into_iter().rev() {
+        backlog.push_front(msg);
+    }
+}
+
+/// Opens the connection a transaction started from `conn_id` runs on, or returns `None`
+/// when it borrows `conn_id` itself.
+fn open_dedicated(
+    conn_pool: &Pool,
+    conn_id: usize,
+    borrow: bool,
+) -> Result<Option<Connection>, Error> {
+    let conn = conn_pool.get(conn_id)?;
+
+    // a dedicated connection to an in-memory database would see an empty database
+    if borrow || conn.path().is_none_or(str::is_empty) {
+        return Ok(None);
+    }
+
+    let (path, options) = conn_pool.source(conn_id)?;
+    Ok(Some(options.open(path)?))
+}
+
+fn begin_transaction(
+    conn_pool: &mut Pool,
+    hooks: &Hooks,
+    conn_id: usize,
+    mode: TransactionMode,
+    dedicated: Option<Connection>,
+) -> Result<usize, Error> {
+    let Some(conn) = dedicated else {
+        conn_pool.get(conn_id)?.execute_batch(mode.begin_sql())?;
+        return conn_pool.borrow(conn_id);
+    };
+
+    let (path, options) = conn_pool.source(conn_id)?;
+    let (path, options) = (path.to_string(), options.clone());
+
+    // changes made by the transaction are reported as the ones of its connection
+    let tracker = hooks.install(&conn, conn_id, conn_pool.tracker(conn_id)?.tracking());
+    conn.execute_batch(mode.begin_sql())?;
+    let conn_id = conn_pool.insert(conn, path, options, tracker);
     Ok(conn_id)
 }
 
+fn serialize(conn_pool: &Pool, conn_id: usize) -> Result<Vec<u8>, Error> {
+    let conn = conn_pool.get(conn_id)?;
+    Ok(conn.serialize(MAIN_DB)?.to_vec())
+}
+
+/// Changes the key of an encrypted database, which every connection opened for it
+/// afterwards uses.
+fn rekey(conn_pool: &Pool, conn_id: usize, key: String) -> Result<(), Error> {
+    let conn = conn_pool.get(conn_id)?;
+    conn.pragma_update(None, "rekey", &key)?;
+    conn_pool.source(conn_id)?.1.set_key(key);
+    Ok(())
+}
+
 fn batch_insert(
-    conn_pool: &mut Slab<Connection>,
+    conn_pool: &Pool,
     conn_id: usize,
-    rows: Rows,
+    rows: Vec<Params>,
     query: String,
-) -> Result<i64, Error> {
-    let conn = get_conn_mut(conn_pool, conn_id)?;
-    let tx = conn.transaction()?;
+    return_rowids: bool,
+) -> Result<(i64, Option<Vec<i64>>), Error> {
+    let conn = conn_pool.get(conn_id)?;
+    // dropping `tx` on any early return, including an interrupted statement,
+    // rolls back every row inserted so far
+    let tx = conn.unchecked_transaction()?;
     let mut affected = 0;
+    let mut rowids = return_rowids.then(|| Vec::with_capacity(rows.len()));
 
     {
         let mut stmt = tx.prepare(&query)?;
-        for row in rows.as_ref().iter() {
-            let params: Vec<&dyn ToSql> = row.as_ref().iter().map(|v| v as &dyn ToSql).collect();
-            let n = stmt.execute(params.as_slice())?;
-            affected += n;
+        for params in rows.iter() {
+            params.bind(&mut stmt)?;
+            let changes = stmt.raw_execute()?;
+            affected += changes;
+            if let Some(rowids) = rowids.as_mut() {
+                // keeps one entry per row, even for rows skipped by `INSERT OR IGNORE`
+                rowids.push(if changes > 0 {
+                    tx.last_insert_rowid()
+                } else {
+                    0
+                });
+            }
         }
     };
 
     tx.commit()?;
-    Ok(affected as i64)
+    Ok((affected as i64, rowids))
 }
 
 fn exec(
-    conn_pool: &Slab<Connection>,
+    conn_pool: &Pool,
     conn_id: usize,
-    params: Row,
+    params: Params,
     query: String,
-) -> Result<i64, Error> {
-    let conn = get_conn(conn_pool, conn_id)?;
+) -> Result<(i64, i64), Error> {
+    let conn = conn_pool.get(conn_id)?;
     let mut stmt = conn.prepare_cached(&query)?;
-    let params: Vec<&dyn ToSql> = params.as_ref().iter().map(|v| v as &dyn ToSql).collect();
-    Ok(stmt.execute(params.as_slice()).map(|v| v as i64)?)
+    execute(conn, &mut stmt, params)
 }
 
-fn fetch(
-    conn_pool: &Slab<Connection>,
+/// Reports the progress of `backup`, keeping it for the next step unless it is `done`.
+fn backup_progress(
+    backups: &mut Slab<BackupState>,
+    backup: BackupState,
+    done: bool,
+) -> BackupProgress {
+    let (pagecount, remaining) = backup.progress();
+    let backup_id = if done { 0 } else { backups.insert(backup) };
+    BackupProgress {
+        backup_id,
+        pagecount,
+        remaining,
+        done,
+    }
+}
+
+/// Finishes the task running a backup once it is done or has failed.
+fn settle_backup(
+    ctx: &TaskContext,
+    result: Result<BackupProgress, Error>,
+) -> Result<BackupProgress, Error> {
+    match result {
+        Ok(progress) if !progress.done => Ok(progress),
+        result => ctx.finish(result),
+    }
+}
+
+fn exec_script(
+    conn_pool: &Pool,
     conn_id: usize,
-    params: Row,
-    query: String,
-) -> Result<(Rows, Columns), Error> {
-    let params: Vec<&dyn ToSql> = params.as_ref().iter().map(|v| v as &dyn ToSql).collect();
-    let conn = get_conn(conn_pool, conn_id)?;
-    let mut stmt = conn.prepare_cached(&query)?;
+    sql: &str,
+    transaction: bool,
+) -> Result<(), Error> {
+    let conn = conn_pool.get(conn_id)?;
+    // a connection already inside a transaction runs the script as part of it
+    let tx = if transaction && conn.is_autocommit() {
+        Some(conn.unchecked_transaction()?)
+    } else {
+        None
+    };
+
+    run_script(conn, sql)?;
+    if let Some(tx) = tx {
+        tx.commit()?;
+    }
+    Ok(())
+}
 
-    let columns: Vec<String> = stmt.column_names().iter().map(|s| s.to_string()).collect();
-    let rows = stmt.query_map(params.as_slice(), |row| Ok(Row::from(row)))?;
-    let result: Result<Rows, _> = rows.collect::<rusqlite::Result<Vec<Row>>>().map(Into::into);
+/// Runs each statement of `sql` in turn, the same way `execute_batch` does, but
+/// keeping count of them so that a failure can be traced back to its statement.
+fn run_script(conn: &Connection, sql: &str) -> Result<(), Error> {
+    let mut batch = Batch::new(conn, sql);
+    let mut index = 0;
+    let locate = |index: usize, err: rusqlite::Error| {
+        // the SQL of the error is the remainder of the script that was being prepared
+        let offset = match &err {
+            rusqlite::Error::SqlInputError {
+                sql: rest, offset, ..
+            } => (sql.len() - rest.len()) as i64 + *offset as i64,
+            _ => -1,
+        };
+        Error::Script(index, offset, Box::new(err.into()))
+    };
 
-    Ok(result.map(|v| (v, columns.into()))?)
+    while let Some(mut stmt) = batch.next().map_err(|err| locate(index, err))? {
+        // like `execute_batch`, statements returning rows (e.g. some PRAGMAs) are
+        // stepped once and their rows discarded
+        stmt.raw_query().next().map_err(|err| locate(index, err))?;
+        index += 1;
+    }
+    Ok(())
 }
 
-fn rollback(
-    conn_pool: &mut Slab<Connection>,
+/// Applies the migrations that haven't been applied yet, in version order and inside a
+/// single transaction, returning their versions.
+///
+/// Applied migrations are recorded in the `aslet_migrations` table along with the
+/// checksum of their script, and `user_version` is set to the highest version applied.
+fn migrate(conn: &Connection, mut migrations: Vec<Migration>) -> Result<Vec<i64>, Error> {
+    migrations.sort_by_key(|m| m.version);
+    if let Some(pair) = migrations.windows(2).find(|w| w[0].version == w[1].version) {
+        let reason = format!("duplicate version {}", pair[0].version);
+        return Err(InternalError::InvalidMigration(reason).into());
+    }
+    if let Some(m) = migrations.first().filter(|m| m.version < 1) {
+        let reason = format!("version {} is not positive", m.version);
+        return Err(InternalError::InvalidMigration(reason).into());
+    }
+
+    // taking the write lock upfront keeps two processes from applying the same migrations
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    tx.execute_batch(
+        "create table if not exists aslet_migrations (
+            version integer primary key,
+            name text not null,
+            checksum text not null,
+            applied_at text not null default current_timestamp
+        )",
+    )?;
+
+    let mut applied = Vec::new();
+    for migration in migrations.iter() {
+        let checksum: Option<String> = tx
+            .query_row(
+                "select checksum from aslet_migrations where version = ?1",
+                [migration.version],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match checksum {
+            Some(checksum) if checksum == migration.checksum => continue,
+            Some(_) => return Err(InternalError::MigrationModified(migration.version).into()),
+            None => {}
+        }
+
+        run_script(&tx, &migration.sql)
+            .map_err(|err| Error::Migration(migration.version, Box::new(err)))?;
+        tx.execute(
+            "insert into aslet_migrations (version, name, checksum) values (?1, ?2, ?3)",
+            (migration.version, &migration.name, &migration.checksum),
+        )?;
+        applied.push(migration.version);
+    }
+
+    let latest: i64 = tx.query_row("select max(version) from aslet_migrations", [], |row| {
+        Ok(row.get::<_, Option<i64>>(0)?.unwrap_or(0))
+    })?;
+    tx.pragma_update(None, "user_version", latest)?;
+    tx.commit()?;
+    Ok(applied)
+}
+
+fn fetch(conn: &Connection, params: Params, sql: String) -> Result<(Rows, Columns), Error> {
+    let mut stmt = conn.prepare_cached(&sql)?;
+    query(&mut stmt, &params)
+}
+
+fn run_live(conn: &Connection, request: &WatchRequest) -> Result<(Rows, Columns, Format), Error> {
+    let mut stmt = conn.prepare_cached(&request.sql)?;
+    query(&mut stmt, &request.params).map(|(rows, columns)| (rows, columns, request.format))
+}
+
+/// Runs `stmt` and returns the number of affected rows along with the rowid of the
+/// last row inserted through `conn`.
+fn execute(conn: &Connection, stmt: &mut Statement, params: Params) -> Result<(i64, i64), Error> {
+    params.bind(stmt)?;
+    let affected = stmt.raw_execute()? as i64;
+    Ok((affected, conn.last_insert_rowid()))
+}
+
+fn query(stmt: &mut Statement, params: &Params) -> Result<(Rows, Columns), Error> {
+    params.bind(stmt)?;
+    let columns = Columns::of(stmt);
+    let mut rows = stmt.raw_query();
+    let mut result = Vec::new();
+    while let Some(row) = rows.next()? {
+        result.push(Row::from(row));
+    }
+
+    Ok((result.into(), columns))
+}
+
+fn prepare(
+    conn_pool: &Pool,
+    statements: &mut Slab<PreparedStatement>,
     conn_id: usize,
-    state: TransactionState,
-) -> Result<(), Error> {
+    query: String,
+) -> Result<(usize, Columns, usize), Error> {
+    let (conn, interrupt) = conn_pool.share(conn_id)?;
+    let stmt = PreparedStatement::new(conn, interrupt, &query)?;
+    let (columns, parameter_count) = (stmt.columns(), stmt.parameter_count());
+    Ok((statements.insert(stmt), columns, parameter_count))
+}
+
+fn get_statement(
+    statements: &mut Slab<PreparedStatement>,
+    stmt_id: usize,
+) -> Result<&mut PreparedStatement, Error> {
+    statements
+        .get_mut(stmt_id)
+        .ok_or_else(|| InternalError::InvalidStatement(stmt_id).into())
+}
+
+fn open_cursor(
+    conn_pool: &Pool,
+    cursors: &mut Slab<Cursor>,
+    conn_id: usize,
+    params: Params,
+    query: String,
+) -> Result<(usize, Columns), Error> {
+    let (conn, interrupt) = conn_pool.share(conn_id)?;
+    let cursor = Cursor::new(conn, &interrupt, &query, params)?;
+    let columns = cursor.columns();
+    Ok((cursors.insert(cursor), columns))
+}
+
+fn rollback(conn_pool: &mut Pool, conn_id: usize, state: TransactionState) -> Result<(), Error> {
     if !state.is_active() {
         return Err(InternalError::InvalidTransaction.into());
     }
 
-    let conn = get_conn(conn_pool, conn_id)?;
+    let (conn, savepoints) = conn_pool.savepoints(conn_id)?;
     conn.execute("ROLLBACK;", []).map_err(Error::from)?;
+    savepoints
+        .drain(..)
+        .for_each(|savepoint| savepoint.rollback());
     state.rollback();
-    conn_pool.remove(conn_id);
     Ok(())
 }
 
-fn commit(
-    conn_pool: &mut Slab<Connection>,
-    conn_id: usize,
-    state: TransactionState,
-) -> Result<(), Error> {
+fn commit(conn_pool: &mut Pool, conn_id: usize, state: TransactionState) -> Result<(), Error> {
     if !state.is_active() {
         return Err(InternalError::InvalidTransaction.into());
     }
 
-    let conn = get_conn(conn_pool, conn_id)?;
+    let (conn, savepoints) = conn_pool.savepoints(conn_id)?;
     conn.execute("COMMIT;", []).map_err(Error::from)?;
+    savepoints
+        .drain(..)
+        .for_each(|savepoint| savepoint.commit());
     state.commit();
-    conn_pool.remove(conn_id);
     Ok(())
 }
+
+fn savepoint(
+    conn_pool: &mut Pool,
+    conn_id: usize,
+    parent: TransactionState,
+) -> Result<TransactionState, Error> {
+    if !parent.is_active() {
+        return Err(InternalError::InvalidTransaction.into());
+    }
+
+    let tracker = conn_pool.tracker(conn_id)?.clone();
+    let (conn, savepoints) = conn_pool.savepoints(conn_id)?;
+    let name = savepoint_name(savepoints.len());
+    conn.execute_batch(&format!("SAVEPOINT {name};"))?;
+    tracker.savepoint();
+    let state = TransactionState::new();
+    savepoints.push(state.clone());
+    Ok(state)
+}
+
+fn release(conn_pool: &mut Pool, conn_id: usize, state: TransactionState) -> Result<(), Error> {
+    let tracker = conn_pool.tracker(conn_id)?.clone();
+    let (conn, savepoints) = conn_pool.savepoints(conn_id)?;
+    let depth = savepoint_depth(savepoints, &state)?;
+
+    conn.execute_batch(&format!("RELEASE {};", savepoint_name(depth)))?;
+    tracker.release(depth);
+    // releasing a savepoint also releases every savepoint nested in it
+    savepoints
+        .drain(depth..)
+        .for_each(|savepoint| savepoint.commit());
+    Ok(())
+}
+
+fn rollback_to(conn_pool: &mut Pool, conn_id: usize, state: TransactionState) -> Result<(), Error> {
+    let tracker = conn_pool.tracker(conn_id)?.clone();
+    let (conn, savepoints) = conn_pool.savepoints(conn_id)?;
+    let depth = savepoint_depth(savepoints, &state)?;
+
+    conn.execute_batch(&format!("ROLLBACK TO {};", savepoint_name(depth)))?;
+    // the rows written since the savepoint started are no longer part of the transaction
+    tracker.rollback_to(depth);
+    // the savepoint itself stays open, but the ones nested in it are gone
+    savepoints
+        .drain(depth + 1..)
+        .for_each(|savepoint| savepoint.rollback());
+    Ok(())
+}
+
+fn savepoint_depth(
+    savepoints: &[TransactionState],
+    state: &TransactionState,
+) -> Result<usize, Error> {
+    savepoints
+        .iter()
+        .position(|savepoint| savepoint.is(state))
+        .filter(|_| state.is_active())
+        .ok_or_else(|| InternalError::InvalidTransaction.into())
+}
+
+/// Returns the name given in SQL to the savepoint at `depth` in the stack.
+///
+/// `RELEASE` and `ROLLBACK TO` act on the innermost savepoint of a name, so the names
+/// given by the user, which may repeat, are only kept for display.
+fn savepoint_name(depth: usize) -> String {
+    format!("aslet_sp_{depth}")
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use rusqlite::Connection;
+
+use crate::{
+    error::Error,
+    types::{Format, Params},
+    worker::{
+        changes::{self, Database, Modified},
+        messages::InputMessage,
+    },
+};
+
+/// Contains all information required to start a live query on the worker thread.
+///
+/// # Fields
+///
+/// * `conn_id` — Id of the pooled connection the query runs on.
+/// * `live_id` — Id given to the query on the main thread, sent back with each result.
+/// * `sql`, `params` — The query and the parameters bound to it on every run.
+/// * `format` — How the rows of each run are converted on the main thread.
+/// * `debounce` — Delay between the first change to a table the query reads and its next run.
+///
+pub struct WatchRequest {
+    pub conn_id: usize,
+    pub live_id: usize,
+    pub sql: String,
+    pub params: Params,
+    pub format: Format,
+    pub debounce: Duration,
+}
+
+/// When a live query runs next.
+enum Schedule {
+    Idle,
+    Due(Instant),
+    /// A run was queued, which sees every change made until it runs.
+    Queued,
+}
+
+struct LiveQuery {
+    request: WatchRequest,
+    database: Database,
+    tables: HashSet<String>,
+    schedule: Schedule,
+}
+
+/// Queries re-run whenever a transaction committed by any connection of the writer
+/// thread modifies a table they read from.
+///
+/// Changes are gathered while messages are handled, and each query reading a modified
+/// table is scheduled to run once its debounce delay elapses, so a burst of writes only
+/// runs it once. The run is queued like any other message for its connection, so it
+/// waits for a transaction that borrowed the connection to end.
+pub struct LiveQueries(HashMap<usize, LiveQuery>);
+
+impl LiveQueries {
+    pub fn new() -> Self {
+        Self(HashMap::new())
+    }
+
+    /// Starts watching the query of `request`, returning it to be run for the first time.
+    ///
+    /// Fails if the query can't be prepared, in which case it isn't watched.
+    pub fn watch(
+        &mut self,
+        conn: &Connection,
+        request: WatchRequest,
+    ) -> Result<&WatchRequest, Error> {
+        let live = LiveQuery {
+            tables: changes::read_tables(conn, &request.sql)?,
+            database: Database::of(conn, request.conn_id),
+            schedule: Schedule::Idle,
+            request,
+        };
+        let live_id = live.request.live_id;
+        self.0.insert(live_id, live);
+        Ok(&self.0[&live_id].request)
+    }
+
+    pub fn unwatch(&mut self, live_id: usize) {
+        self.0.remove(&live_id);
+    }
+
+    /// Returns the query `live_id` for its queued run, unless it stopped being watched
+    /// meanwhile.
+    pub fn start(&mut self, live_id: usize) -> Option<&WatchRequest> {
+        let live = self.0.get_mut(&live_id)?;
+        live.schedule = Schedule::Idle;
+        Some(&live.request)
+    }
+
+    /// Schedules the queries reading any of the `modified` tables.
+    pub fn mark(&mut self, modified: Vec<Modified>, now: Instant) {
+        for (database, tables) in modified {
+            self.schedule(now, |live| {
+                live.database == database && !live.tables.is_disjoint(&tables)
+            });
+        }
+    }
+
+    /// Schedules every query reading from `database`, whose whole content was replaced.
+    pub fn mark_all(&mut self, database: &Database, now: Instant) {
+        self.schedule(now, |live| live.database == *database);
+    }
+
+    /// Returns when the next scheduled query is due, if any is.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.0
+            .values()
+            .filter_map(|live| match live.schedule {
+                Schedule::Due(due) => Some(due),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Queues a run of every query that is due.
+    pub fn take_due(&mut self, now: Instant) -> Vec<InputMessage> {
+        let mut runs = Vec::new();
+        for (live_id, live) in self.0.iter_mut() {
+            if matches!(live.schedule, Schedule::Due(due) if due <= now) {
+                live.schedule = Schedule::Queued;
+                runs.push(InputMessage::RunLive(live.request.conn_id, *live_id));
+            }
+        }
+        runs
+    }
+
+    fn schedule(&mut self, now: Instant, reads: impl Fn(&LiveQuery) -> bool) {
+        for live in self.0.values_mut() {
+            // later changes are coalesced into the run already scheduled
+            if matches!(live.schedule, Schedule::Idle) && reads(live) {
+                live.schedule = Schedule::Due(now + live.request.debounce);
+            }
+        }
+    }
+}
@@ -1,34 +1,130 @@
+use std::sync::Arc;
+
 use crate::{
-    api::transaction::TransactionState,
-    backup::{BackupRequest, BackupState},
+    api::transaction::{TransactionMode, TransactionState},
+    backup::{BackupProgress, BackupRequest},
     error::Error,
+    migration::Migration,
+    options::OpenOptions,
     tasks::TaskContext,
-    types::{Columns, Row, Rows},
+    types::{Columns, Format, Params, Rows},
+    worker::{
+        ReaderSet, Schema,
+        changes::{ChangeTracking, Changes},
+        live::WatchRequest,
+    },
 };
 
 pub enum InputMessage {
-    Open(TaskContext, String),
-    BatchInsert(usize, TaskContext, String, Rows),
-    Exec(usize, TaskContext, String, Row),
-    Fetch(usize, TaskContext, String, Row),
+    Open(TaskContext, String, OpenOptions),
+    OpenBytes(TaskContext, Vec<u8>, OpenOptions),
+    BatchInsert(usize, TaskContext, String, Vec<Params>, bool),
+    Exec(usize, TaskContext, String, Params),
+    ExecScript(usize, TaskContext, String, bool),
+    Migrate(usize, TaskContext, Vec<Migration>),
+    Schema(usize, TaskContext, Schema, Format),
+    Serialize(usize, TaskContext),
+    Rekey(usize, TaskContext, String),
+    TrackChanges(usize, TaskContext, ChangeTracking),
+    Fetch(usize, TaskContext, String, Params, Format),
+    OpenCursor(usize, TaskContext, String, Params, i64, Format),
+    CursorNext(usize, TaskContext, usize, usize, Format),
+    Prepare(usize, TaskContext, String, i64, Format),
+    ExecStatement(usize, TaskContext, usize, Params),
+    FetchStatement(usize, TaskContext, usize, Params, Format),
+    Watch(WatchRequest),
+    RunLive(usize, usize),
 
-    BeginTransaction(TaskContext, String),
+    BeginTransaction(TaskContext, usize, TransactionMode, bool, i64, Format),
     Rollback(TaskContext, usize, TransactionState),
     Commit(TaskContext, usize, TransactionState),
+    Savepoint(TaskContext, usize, TransactionState, String, i64, Format),
+    Release(TaskContext, usize, TransactionState),
+    RollbackTo(TaskContext, usize, TransactionState),
     BeginBackup(TaskContext, BackupRequest),
-    BackupStep(TaskContext, BackupState),
+    BackupStep(TaskContext, usize),
 
     CloseConn(usize),
-    Quit,
+    CloseCursor(usize, usize),
+    CloseStatement(usize),
+    Unwatch(usize),
+}
+
+impl InputMessage {
+    /// Returns the id of the connection this message is addressed to, if any.
+    pub fn conn_id(&self) -> Option<usize> {
+        match self {
+            InputMessage::BatchInsert(conn_id, ..)
+            | InputMessage::Exec(conn_id, ..)
+            | InputMessage::ExecScript(conn_id, ..)
+            | InputMessage::Migrate(conn_id, ..)
+            | InputMessage::Schema(conn_id, ..)
+            | InputMessage::Serialize(conn_id, _)
+            | InputMessage::Rekey(conn_id, ..)
+            | InputMessage::TrackChanges(conn_id, ..)
+            | InputMessage::Fetch(conn_id, ..)
+            | InputMessage::OpenCursor(conn_id, ..)
+            | InputMessage::CursorNext(conn_id, ..)
+            | InputMessage::Prepare(conn_id, ..)
+            | InputMessage::ExecStatement(conn_id, ..)
+            | InputMessage::FetchStatement(conn_id, ..)
+            | InputMessage::RunLive(conn_id, _)
+            | InputMessage::BeginTransaction(_, conn_id, ..)
+            | InputMessage::Rollback(_, conn_id, _)
+            | InputMessage::Commit(_, conn_id, _)
+            | InputMessage::Savepoint(_, conn_id, ..)
+            | InputMessage::Release(_, conn_id, ..)
+            | InputMessage::RollbackTo(_, conn_id, ..)
+            | InputMessage::CloseConn(conn_id)
+            | InputMessage::CloseCursor(conn_id, _) => Some(*conn_id),
+            InputMessage::BeginBackup(_, request) => Some(request.conn_id),
+            InputMessage::Watch(request) => Some(request.conn_id),
+            InputMessage::Open(..)
+            | InputMessage::OpenBytes(..)
+            | InputMessage::BackupStep(..)
+            | InputMessage::CloseStatement(..)
+            | InputMessage::Unwatch(..) => None,
+        }
+    }
 }
 
 pub enum OutputMessage {
-    Open(TaskContext, Result<(usize, String), Error>),
-    Exec(TaskContext, Result<i64, Error>),
-    Fetch(TaskContext, Result<(Rows, Columns), Error>),
-    TransactionStarted(TaskContext, Result<usize, Error>),
+    Open(TaskContext, Result<(usize, Option<Arc<ReaderSet>>), Error>),
+    Exec(TaskContext, Result<(i64, i64), Error>),
+    BatchInserted(TaskContext, Result<(i64, Option<Vec<i64>>), Error>),
+    Script(TaskContext, Result<(), Error>),
+    Migrated(TaskContext, Result<Vec<i64>, Error>),
+    Serialized(TaskContext, Result<Vec<u8>, Error>),
+    Rekeyed(TaskContext, Result<(), Error>),
+    ChangesTracked(TaskContext, Result<(), Error>),
+    /// Changes made through the connection `conn_id` of the writer `writer_id`, in that
+    /// order, not tied to any task.
+    Changes(usize, usize, Changes),
+    Fetch(TaskContext, Result<(Rows, Columns, Format), Error>),
+    /// A run of the live query with the given id, not tied to any task.
+    LiveChanged(usize, Result<(Rows, Columns, Format), Error>),
+    CursorOpened(
+        TaskContext,
+        Result<(usize, usize, Columns, i64, Format), Error>,
+    ),
+    Prepared(
+        TaskContext,
+        Result<(usize, usize, Columns, usize, i64, Format), Error>,
+    ),
+    TransactionStarted(TaskContext, Result<(usize, i64, Format), Error>),
     TransactionCommitted(TaskContext, Result<(), Error>),
     TransactionRolledBack(TaskContext, Result<(), Error>),
-    Backup(TaskContext, Result<BackupState, Error>),
+    SavepointStarted(
+        TaskContext,
+        Result<(usize, String, TransactionState, i64, Format), Error>,
+    ),
+    SavepointReleased(TaskContext, Result<(), Error>),
+    SavepointRolledBack(TaskContext, Result<(), Error>),
+    Backup(TaskContext, Result<BackupProgress, Error>),
     Canceled(TaskContext),
+    Failed(TaskContext, Error),
+}
+
+pub enum ReadMessage {
+    Fetch(Arc<ReaderSet>, TaskContext, String, Params, Format),
 }
@@ -1,5 +1,14 @@
+pub mod changes;
+mod cursor;
 mod dispatch;
+pub mod live;
 pub mod messages;
+mod pool;
+mod schema;
 mod state;
+mod statement;
+mod timer;
 
+pub use pool::ReaderSet;
+pub use schema::Schema;
 pub use state::Worker;
@@ -0,0 +1,417 @@
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{Connection, InterruptHandle};
+use slab::Slab;
+
+use crate::{
+    api::transaction::TransactionState,
+    error::{Error, InternalError},
+    options::OpenOptions,
+    worker::{changes::Tracker, messages::InputMessage},
+};
+
+/// A read-only connection owned by a reader thread, along with the handle used to interrupt it.
+pub struct PooledConn {
+    pub conn: Connection,
+    pub interrupt: Arc<InterruptHandle>,
+    key: Option<String>,
+}
+
+/// The handle used to interrupt a connection, which is withheld while a cursor is open
+/// on it.
+///
+/// SQLite interrupts every statement running on the connection, and keeps failing the
+/// ones started afterwards until none is running anymore. A cursor keeps its statement
+/// running between batches, so interrupting a task would also fail the cursor, along
+/// with every task run on the connection until the cursor is closed.
+#[derive(Clone)]
+pub struct Interrupt {
+    handle: Arc<InterruptHandle>,
+    cursors: Rc<Cell<usize>>,
+}
+
+impl Interrupt {
+    fn new(conn: &Connection) -> Self {
+        Self {
+            handle: Arc::new(conn.get_interrupt_handle()),
+            cursors: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Returns the handle, unless a cursor is open on the connection.
+    pub fn get(&self) -> Option<Arc<InterruptHandle>> {
+        (self.cursors.get() == 0).then(|| self.handle.clone())
+    }
+
+    /// Counts a cursor as open on the connection until the returned guard is dropped.
+    pub fn open_cursor(&self) -> OpenCursor {
+        self.cursors.set(self.cursors.get() + 1);
+        OpenCursor(self.cursors.clone())
+    }
+}
+
+/// Keeps a cursor counted as open on its connection, see [`Interrupt`].
+pub struct OpenCursor(Rc<Cell<usize>>);
+
+impl Drop for OpenCursor {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+struct Entry {
+    // shared with the cursors and statements reading from the connection,
+    // which keep it open until they are dropped
+    conn: Rc<Connection>,
+    interrupt: Interrupt,
+    path: String,
+    options: Arc<OpenOptions>,
+    borrowed_by: Option<usize>,
+    // the connection is the destination of a restore in progress
+    restoring: bool,
+    pending: VecDeque<InputMessage>,
+    savepoints: Vec<TransactionState>,
+    tracker: Tracker,
+}
+
+enum Slot {
+    Conn(Box<Entry>),
+    /// A transaction running on the connection with the given id.
+    Borrowed(usize),
+}
+
+/// Connections opened by the worker, indexed by the id handed to the main thread.
+///
+/// Each connection remembers the path and [`OpenOptions`] it was opened with, so that
+/// connections opened later for the same database are configured identically.
+///
+/// A connection can also be borrowed by a transaction, which gets an id of its own
+/// that resolves to the borrowed connection. While borrowed, messages addressed to
+/// the connection itself are deferred until the transaction ends. The same happens
+/// while a restore replaces the database of the connection.
+pub struct Pool(Slab<Slot>);
+
+impl Pool {
+    pub fn new() -> Self {
+        Self(Slab::new())
+    }
+
+    /// Returns the id the next connection inserted gets.
+    pub fn next_id(&self) -> usize {
+        self.0.vacant_key()
+    }
+
+    pub fn insert(
+        &mut self,
+        conn: Connection,
+        path: String,
+        options: Arc<OpenOptions>,
+        tracker: Tracker,
+    ) -> usize {
+        self.0.insert(Slot::Conn(Box::new(Entry {
+            interrupt: Interrupt::new(&conn),
+            conn: Rc::new(conn),
+            path,
+            options,
+            borrowed_by: None,
+            restoring: false,
+            pending: VecDeque::new(),
+            savepoints: Vec::new(),
+            tracker,
+        })))
+    }
+
+    pub fn remove(&mut self, conn_id: usize) -> Option<Rc<Connection>> {
+        match self.0.get(conn_id) {
+            Some(Slot::Conn(_)) => match self.0.remove(conn_id) {
+                Slot::Conn(entry) => Some(entry.conn),
+                Slot::Borrowed(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Lends the given connection to a transaction, returning the id the transaction uses.
+    pub fn borrow(&mut self, conn_id: usize) -> Result<usize, Error> {
+        self.entry(conn_id)?;
+        let tx_id = self.0.insert(Slot::Borrowed(conn_id));
+        if let Some(Slot::Conn(entry)) = self.0.get_mut(conn_id) {
+            entry.borrowed_by = Some(tx_id);
+        }
+        Ok(tx_id)
+    }
+
+    /// Ends the transaction with the given id.
+    ///
+    /// A dedicated connection is closed, while a borrowed one is handed back to its
+    /// owner, returning the messages deferred while it was borrowed.
+    pub fn end_transaction(&mut self, tx_id: usize) -> VecDeque<InputMessage> {
+        match self.0.get(tx_id) {
+            Some(Slot::Conn(_)) => {
+                self.remove(tx_id);
+                VecDeque::new()
+            }
+            Some(Slot::Borrowed(owner)) => {
+                let owner = *owner;
+                self.0.remove(tx_id);
+                match self.0.get_mut(owner) {
+                    Some(Slot::Conn(entry)) => {
+                        entry.borrowed_by = None;
+                        std::mem::take(&mut entry.pending)
+                    }
+                    _ => VecDeque::new(),
+                }
+            }
+            None => VecDeque::new(),
+        }
+    }
+
+    /// Marks the given connection as the destination of a restore, deferring the
+    /// messages addressed to it until [`end_restore`](Self::end_restore).
+    ///
+    /// Fails if a cursor, statement or backup still uses the connection, since it would
+    /// read the database while it is being replaced.
+    pub fn begin_restore(&mut self, conn_id: usize) -> Result<(), Error> {
+        let entry = self.entry_mut(conn_id)?;
+        if Rc::strong_count(&entry.conn) > 1 {
+            return Err(InternalError::ConnectionInUse(conn_id).into());
+        }
+        entry.restoring = true;
+        Ok(())
+    }
+
+    /// Ends the restore of the given connection, returning the messages deferred meanwhile.
+    pub fn end_restore(&mut self, conn_id: usize) -> VecDeque<InputMessage> {
+        match self.0.get_mut(conn_id) {
+            Some(Slot::Conn(entry)) if entry.restoring => {
+                entry.restoring = false;
+                std::mem::take(&mut entry.pending)
+            }
+            _ => VecDeque::new(),
+        }
+    }
+
+    /// Defers `msg` if it is addressed to a connection that is currently borrowed or
+    /// being restored.
+    pub fn defer(&mut self, conn_id: usize, msg: InputMessage) -> Option<InputMessage> {
+        match self.0.get_mut(conn_id) {
+            Some(Slot::Conn(entry)) if entry.borrowed_by.is_some() || entry.restoring => {
+                entry.pending.push_back(msg);
+                None
+            }
+            _ => Some(msg),
+        }
+    }
+
+    pub fn get(&self, conn_id: usize) -> Result<&Connection, Error> {
+        self.entry(conn_id).map(|entry| entry.conn.as_ref())
+    }
+
+    /// Returns a handle to the given connection that keeps it open even after it is
+    /// removed from the pool, along with its interrupt handle.
+    pub fn share(&self, conn_id: usize) -> Result<(Rc<Connection>, Interrupt), Error> {
+        self.entry(conn_id)
+            .map(|entry| (entry.conn.clone(), entry.interrupt.clone()))
+    }
+
+    /// Returns the given connection along with the stack of savepoints open on it,
+    /// innermost last.
+    pub fn savepoints(
+        &mut self,
+        conn_id: usize,
+    ) -> Result<(&Connection, &mut Vec<TransactionState>), Error> {
+        self.entry_mut(conn_id)
+            .map(|entry| (entry.conn.as_ref(), &mut entry.savepoints))
+    }
+
+    /// Returns the path and options the given connection was opened with.
+    pub fn source(&self, conn_id: usize) -> Result<(&str, &Arc<OpenOptions>), Error> {
+        self.entry(conn_id)
+            .map(|entry| (entry.path.as_str(), &entry.options))
+    }
+
+    /// Returns the handle to the hooks of the given connection, whose tracking mode also
+    /// applies to the dedicated connections of the transactions started afterwards.
+    pub fn tracker(&self, conn_id: usize) -> Result<&Tracker, Error> {
+        self.entry(conn_id).map(|entry| &entry.tracker)
+    }
+
+    /// Returns the interrupt handle of the given connection, if it is still open and no
+    /// cursor is open on it.
+    pub fn interrupt(&self, conn_id: usize) -> Option<Arc<InterruptHandle>> {
+        self.entry(conn_id)
+            .ok()
+            .and_then(|entry| entry.interrupt.get())
+    }
+
+    fn entry(&self, conn_id: usize) -> Result<&Entry, Error> {
+        let slot = match self.0.get(conn_id) {
+            Some(Slot::Borrowed(owner)) => self.0.get(*owner),
+            slot => slot,
+        };
+        match slot {
+            Some(Slot::Conn(entry)) => Ok(entry),
+            _ => Err(InternalError::InvalidConnection(conn_id).into()),
+        }
+    }
+
+    fn entry_mut(&mut self, conn_id: usize) -> Result<&mut Entry, Error> {
+        let id = match self.0.get(conn_id) {
+            Some(Slot::Borrowed(owner)) => *owner,
+            _ => conn_id,
+        };
+        match self.0.get_mut(id) {
+            Some(Slot::Conn(entry)) => Ok(entry),
+            _ => Err(InternalError::InvalidConnection(conn_id).into()),
+        }
+    }
+}
+
+/// Read-only connections to a database in WAL mode, shared by the reader threads.
+///
+/// Connections are opened on demand by whichever reader thread needs one and returned
+/// after each query, so there are never more of them than reader threads. They are
+/// closed once the owning [`AsletConn`](crate::api::conn::AsletConn) and every
+/// queued read referencing the set are dropped.
+pub struct ReaderSet {
+    path: String,
+    options: Arc<OpenOptions>,
+    idle: Mutex<Vec<PooledConn>>,
+}
+
+impl ReaderSet {
+    pub fn new(path: String, options: Arc<OpenOptions>) -> Self {
+        Self {
+            path,
+            options,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn acquire(&self) -> Result<PooledConn, Error> {
+        let key = self.options.key();
+        {
+            let mut idle = self.idle.lock().unwrap();
+            // connections opened before the database was rekeyed can't decrypt it anymore
+            idle.retain(|pooled| pooled.key == key);
+            if let Some(pooled) = idle.pop() {
+                return Ok(pooled);
+            }
+        }
+
+        let conn = self.options.open_reader(&self.path)?;
+        let interrupt = Arc::new(conn.get_interrupt_handle());
+        Ok(PooledConn {
+            conn,
+            interrupt,
+            key,
+        })
+    }
+
+    pub fn release(&self, pooled: PooledConn) {
+        self.idle.lock().unwrap().push(pooled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tasks::TaskContext,
+        types::Params,
+        worker::{
+            changes::{ChangeTracking, Hooks},
+            cursor::Cursor,
+        },
+    };
+
+    fn pool() -> (Pool, usize) {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1), (2), (3);")
+            .unwrap();
+
+        let (sender, _) = crossbeam::channel::unbounded();
+        let mut pool = Pool::new();
+        let tracker = Hooks::new(sender, 1).install(&conn, pool.next_id(), ChangeTracking::Off);
+        let options = Arc::new(OpenOptions::default());
+        let conn_id = pool.insert(conn, String::new(), options, tracker);
+        (pool, conn_id)
+    }
+
+    fn open_cursor(pool: &Pool, conn_id: usize) -> Cursor {
+        let (conn, interrupt) = pool.share(conn_id).unwrap();
+        Cursor::new(
+            conn,
+            &interrupt,
+            "SELECT x FROM t",
+            Params::Named(Vec::new()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn interrupt_is_withheld_while_a_cursor_is_open() {
+        let (pool, conn_id) = pool();
+        assert!(pool.interrupt(conn_id).is_some());
+
+        let cursor = open_cursor(&pool, conn_id);
+        let other = open_cursor(&pool, conn_id);
+        assert!(pool.interrupt(conn_id).is_none());
+
+        drop(cursor);
+        assert!(pool.interrupt(conn_id).is_none());
+        drop(other);
+        assert!(pool.interrupt(conn_id).is_some());
+    }
+
+    #[test]
+    fn interrupting_fails_the_cursor_and_the_statements_after_it() {
+        let (pool, conn_id) = pool();
+        let (conn, interrupt) = pool.share(conn_id).unwrap();
+        let mut cursor = open_cursor(&pool, conn_id);
+        cursor.next_batch(1).unwrap();
+
+        // the handle the connection had before it withheld it
+        interrupt.handle.interrupt();
+        assert!(conn.execute("INSERT INTO t VALUES (4)", []).is_err());
+        assert!(cursor.next_batch(1).is_err());
+    }
+
+    #[test]
+    fn canceling_a_task_keeps_the_cursors_of_its_connection() {
+        let (pool, conn_id) = pool();
+        let mut cursor = open_cursor(&pool, conn_id);
+        cursor.next_batch(1).unwrap();
+
+        // canceled while running, and again as a late cancel racing `finish`
+        let task_ctx = TaskContext::new(0, None);
+        task_ctx.start(pool.interrupt(conn_id)).unwrap();
+        assert!(task_ctx.cancel());
+        let result = pool
+            .get(conn_id)
+            .unwrap()
+            .execute("INSERT INTO t VALUES (4)", []);
+        assert_eq!(task_ctx.finish(result.map_err(Error::from)).unwrap(), 1);
+
+        let task_ctx = TaskContext::new(1, None);
+        task_ctx.start(pool.interrupt(conn_id)).unwrap();
+        let result = pool
+            .get(conn_id)
+            .unwrap()
+            .execute("INSERT INTO t VALUES (5)", []);
+        assert!(task_ctx.cancel());
+        assert_eq!(task_ctx.finish(result.map_err(Error::from)).unwrap(), 1);
+
+        assert!(cursor.next_batch(10).is_ok());
+        let result = pool
+            .get(conn_id)
+            .unwrap()
+            .execute("INSERT INTO t VALUES (6)", []);
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,95 @@
+use rusqlite::Connection;
+
+use crate::{
+    error::Error,
+    types::{Columns, Row, Rows},
+};
+
+/// A description of the database schema, read from `sqlite_schema` and the schema pragmas.
+///
+/// Each query returns one row per object, shaped into dictionaries on the main thread.
+/// Flags and lists are computed by the queries themselves, so their columns are given a
+/// declared type (`BOOLEAN` or `JSON`) for the usual decoding to turn them into `bool`
+/// and `Array` values.
+pub enum Schema {
+    Tables,
+    Views,
+    Columns(String),
+    Indexes(String),
+    ForeignKeys(String),
+}
+
+impl Schema {
+    fn sql(&self) -> &'static str {
+        match self {
+            Schema::Tables => {
+                r#"select t.name, t.ncol as column_count, t.wr as without_rowid, t.strict, s.sql
+                from pragma_table_list as t
+                join sqlite_schema as s on s.type = 'table' and s.name = t.name
+                where t.schema = 'main' and t.type = 'table' and substr(t.name, 1, 7) <> 'sqlite_'
+                order by t.name"#
+            }
+            Schema::Views => {
+                r#"select name, sql from sqlite_schema where type = 'view' order by name"#
+            }
+            Schema::Columns(_) => {
+                r#"select name, type, not "notnull" as nullable, dflt_value as "default",
+                    pk, hidden = 1 as hidden, hidden in (2, 3) as generated
+                from pragma_table_xinfo(?1)
+                order by cid"#
+            }
+            Schema::Indexes(_) => {
+                r#"select l.name, l."unique", l.origin, l.partial,
+                    (select json_group_array(i.name)
+                        from (select name from pragma_index_info(l.name) order by seqno) as i
+                    ) as columns
+                from pragma_index_list(?1) as l
+                order by l.name"#
+            }
+            Schema::ForeignKeys(_) => {
+                r#"select "table", json_group_array("from") as "from", json_group_array("to") as "to",
+                    on_update, on_delete
+                from (select * from pragma_foreign_key_list(?1) order by id, seq)
+                group by id
+                order by id"#
+            }
+        }
+    }
+
+    fn decltypes(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Schema::Tables => &[("without_rowid", "BOOLEAN"), ("strict", "BOOLEAN")],
+            Schema::Views => &[],
+            Schema::Columns(_) => &[
+                ("nullable", "BOOLEAN"),
+                ("hidden", "BOOLEAN"),
+                ("generated", "BOOLEAN"),
+            ],
+            Schema::Indexes(_) => &[
+                ("unique", "BOOLEAN"),
+                ("partial", "BOOLEAN"),
+                ("columns", "JSON"),
+            ],
+            Schema::ForeignKeys(_) => &[("from", "JSON"), ("to", "JSON")],
+        }
+    }
+
+    pub fn fetch(&self, conn: &Connection) -> Result<(Rows, Columns), Error> {
+        let mut stmt = conn.prepare_cached(self.sql())?;
+        if let Schema::Columns(table) | Schema::Indexes(table) | Schema::ForeignKeys(table) = self {
+            stmt.raw_bind_parameter(1, table)?;
+        }
+
+        let mut columns = Columns::of(&stmt);
+        for (name, decltype) in self.decltypes() {
+            columns.declare(name, decltype);
+        }
+
+        let mut rows = stmt.raw_query();
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            result.push(Row::from(row));
+        }
+        Ok((result.into(), columns))
+    }
+}
@@ -1,31 +1,60 @@
 use std::{
-    rc::Rc,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::{Rc, Weak},
     thread::{JoinHandle, spawn},
 };
 
 use crossbeam::channel::{Receiver, Sender};
-use godot::{global::printerr, meta::ToGodot};
+use godot::{classes::ProjectSettings, global::printerr, meta::ToGodot, obj::Singleton};
 
-use crate::worker::messages::{InputMessage, OutputMessage};
+use crate::{
+    error::Error,
+    tasks::TaskContext,
+    worker::{
+        messages::{InputMessage, OutputMessage, ReadMessage},
+        timer::Timer,
+    },
+};
 
-use super::dispatch::message_loop;
+use super::dispatch::{message_loop, reader_loop};
 
 #[derive(Debug)]
 struct InnerState {
-    handle: Option<JoinHandle<()>>,
-    input_sender: Sender<InputMessage>,
+    reader_handles: Vec<JoinHandle<()>>,
+    read_sender: Option<Sender<ReadMessage>>,
+    output_sender: Sender<OutputMessage>,
+    timer: Timer,
+    /// Writer threads of the databases opened by path, shared by every connection to them.
+    writers: RefCell<HashMap<String, Weak<Writer>>>,
+    /// Writer threads no handle refers to anymore, joined along with the readers.
+    stopped: RefCell<Vec<JoinHandle<()>>>,
+    next_writer_id: Cell<usize>,
 }
 
 impl InnerState {
-    pub fn new() -> (Self, Receiver<OutputMessage>) {
-        let (input_sender, input_receiver) = crossbeam::channel::unbounded::<InputMessage>();
+    pub fn new(readers: usize) -> (Self, Receiver<OutputMessage>) {
+        let (read_sender, read_receiver) = crossbeam::channel::unbounded::<ReadMessage>();
         let (output_sender, output_receiver) = crossbeam::channel::unbounded::<OutputMessage>();
-        let handle = Some(spawn(move || message_loop(input_receiver, output_sender)));
+        let timer = Timer::new(output_sender.clone());
+
+        let reader_handles = (0..readers)
+            .map(|_| {
+                let read_receiver = read_receiver.clone();
+                let output_sender = output_sender.clone();
+                spawn(move || reader_loop(read_receiver, output_sender))
+            })
+            .collect();
 
         (
             Self {
-                handle,
-                input_sender,
+                reader_handles,
+                read_sender: (readers > 0).then_some(read_sender),
+                output_sender,
+                timer,
+                writers: RefCell::new(HashMap::new()),
+                stopped: RefCell::new(Vec::new()),
+                next_writer_id: Cell::new(1),
             },
             output_receiver,
         )
@@ -34,30 +63,189 @@ impl InnerState {
 
 impl Drop for InnerState {
     fn drop(&mut self) {
-        let _ = self.input_sender.send(InputMessage::Quit);
-        match self.handle.take() {
-            Some(handle) => {
-                if let Err(_) = handle.join() {
-                    printerr(&["worker thread panicked".to_variant()]);
-                }
-            }
-            None => {}
+        self.read_sender.take();
+
+        let stopped = self.stopped.get_mut().drain(..);
+        for handle in stopped.chain(self.reader_handles.drain(..)) {
+            join(handle);
+        }
+    }
+}
+
+/// A writer thread, which holds the read-write connections of one database and executes
+/// their messages in order.
+///
+/// The thread stops once every handle to it is dropped, after running the messages
+/// already sent to it.
+#[derive(Debug)]
+struct Writer {
+    id: usize,
+    state: Rc<InnerState>,
+    path: Option<String>,
+    input_sender: Sender<InputMessage>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    fn spawn(state: Rc<InnerState>, path: Option<String>) -> Self {
+        let (input_sender, input_receiver) = crossbeam::channel::unbounded::<InputMessage>();
+        let output_sender = state.output_sender.clone();
+        let with_readers = state.read_sender.is_some();
+        let id = state.next_writer_id.get();
+        state.next_writer_id.set(id + 1);
+        let handle = Some(spawn(move || {
+            message_loop(input_receiver, output_sender, with_readers, id)
+        }));
+        Self {
+            id,
+            state,
+            path,
+            input_sender,
+            handle,
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.as_ref() {
+            self.state.writers.borrow_mut().remove(path);
         }
+
+        // the thread is only joined once the worker is, since it may still be running;
+        // the ones that already finished are joined now so they don't pile up
+        let mut stopped = self.state.stopped.borrow_mut();
+        let (finished, running) = stopped
+            .drain(..)
+            .chain(self.handle.take())
+            .partition::<Vec<_>, _>(JoinHandle::is_finished);
+        *stopped = running;
+        finished.into_iter().for_each(join);
+    }
+}
+
+fn join(handle: JoinHandle<()>) {
+    if handle.join().is_err() {
+        printerr(&["worker thread panicked".to_variant()]);
     }
 }
 
+/// Handle to the worker threads.
+///
+/// Each database has its own writer thread, which holds its read-write connections and
+/// executes their messages in order, so a slow write to one database doesn't hold up
+/// the others. An optional pool of reader threads, shared by every database, executes
+/// [`ReadMessage`]s concurrently on read-only connections.
+///
+/// The `Worker` of an [`Aslet`](crate::api::aslet::Aslet) isn't tied to a writer; the
+/// ones handed to connections and the objects created from them are, through
+/// [`writer`](Self::writer).
 #[derive(Debug, Clone)]
-pub struct Worker(Rc<InnerState>);
+pub struct Worker {
+    state: Rc<InnerState>,
+    writer: Option<Rc<Writer>>,
+}
 
 impl Worker {
-    pub fn new() -> (Self, Receiver<OutputMessage>) {
-        let (inner_state, receiver) = InnerState::new();
-        (Self(Rc::new(inner_state)), receiver)
+    pub fn new(readers: usize) -> (Self, Receiver<OutputMessage>) {
+        let (inner_state, receiver) = InnerState::new(readers);
+        let worker = Self {
+            state: Rc::new(inner_state),
+            writer: None,
+        };
+        (worker, receiver)
+    }
+
+    /// Returns a handle to the writer thread of the database at `path`, spawning it if
+    /// the database has none yet.
+    ///
+    /// Every connection opened with the same path shares the writer, while each unnamed
+    /// in-memory database (`""` or `":memory:"`) and each database opened from bytes
+    /// gets its own.
+    pub fn writer(&self, path: Option<&str>) -> Worker {
+        let path = path
+            .filter(|path| !path.is_empty() && *path != ":memory:")
+            .map(|path| {
+                ProjectSettings::singleton()
+                    .globalize_path(path)
+                    .to_string()
+            });
+
+        let existing = path.as_ref().and_then(|path| {
+            self.state
+                .writers
+                .borrow()
+                .get(path)
+                .and_then(Weak::upgrade)
+        });
+        let writer = existing.unwrap_or_else(|| {
+            let writer = Rc::new(Writer::spawn(self.state.clone(), path.clone()));
+            if let Some(path) = path {
+                self.state
+                    .writers
+                    .borrow_mut()
+                    .insert(path, Rc::downgrade(&writer));
+            }
+            writer
+        });
+
+        Worker {
+            state: self.state.clone(),
+            writer: Some(writer),
+        }
+    }
+
+    /// Returns the id of the writer thread, which tells apart connections of different
+    /// writers that got the same id, or `0` if the handle isn't tied to a writer.
+    pub fn writer_id(&self) -> usize {
+        self.writer.as_ref().map_or(0, |writer| writer.id)
     }
 
     pub fn send(&self, msg: InputMessage) {
-        if let Err(_) = self.0.input_sender.send(msg) {
+        let sent = match self.writer.as_ref() {
+            Some(writer) => writer.input_sender.send(msg).is_ok(),
+            None => false,
+        };
+        if !sent {
             printerr(&["failed to contact worker, might have panicked".to_variant()]);
         }
     }
+
+    /// Starts the deadline of a task, if it has one, as soon as it is created.
+    ///
+    /// A task still queued once its deadline passes is failed right away, rather than
+    /// once the worker gets to it.
+    pub fn watch(&self, task_ctx: &TaskContext) {
+        self.state.timer.watch(task_ctx);
+    }
+
+    /// Fails a task without involving the worker threads.
+    ///
+    /// Used when a request is found to be invalid on the main thread; the failure is
+    /// still delivered through [`Aslet::poll`](crate::api::aslet::Aslet::poll), like
+    /// any other result.
+    pub fn reject(&self, task_ctx: TaskContext, err: Error) {
+        let _ = self
+            .state
+            .output_sender
+            .send(OutputMessage::Failed(task_ctx, err));
+    }
+
+    /// Fails a live query without involving the worker threads, like [`reject`](Self::reject).
+    pub fn reject_live(&self, live_id: usize, err: Error) {
+        let _ = self
+            .state
+            .output_sender
+            .send(OutputMessage::LiveChanged(live_id, Err(err)));
+    }
+
+    pub fn send_read(&self, msg: ReadMessage) {
+        let sent = match self.state.read_sender.as_ref() {
+            Some(sender) => sender.send(msg).is_ok(),
+            None => false,
+        };
+        if !sent {
+            printerr(&["failed to contact reader threads".to_variant()]);
+        }
+    }
 }
@@ -0,0 +1,60 @@
+use std::{rc::Rc, sync::Arc};
+
+use rusqlite::{Connection, InterruptHandle, Statement};
+
+use crate::{error::Error, types::Columns, worker::pool::Interrupt};
+
+/// A statement prepared on behalf of an `AsletStatement`, kept alive on the worker
+/// until the Godot object is dropped.
+///
+/// # Safety
+///
+/// The `Statement` borrows the `Connection` it was prepared on, and that borrow is
+/// extended to `'static` with `std::mem::transmute` so both can be stored together.
+/// This is sound for the same reasons as with [`Cursor`](super::cursor::Cursor): the
+/// `Rc` in `conn` keeps the connection open, `stmt` is declared first so it is
+/// finalized before `conn` is released, and the struct never leaves the worker thread.
+pub struct PreparedStatement {
+    stmt: Statement<'static>,
+    conn: Rc<Connection>,
+    interrupt: Interrupt,
+}
+
+impl PreparedStatement {
+    pub fn new(conn: Rc<Connection>, interrupt: Interrupt, query: &str) -> Result<Self, Error> {
+        let stmt = unsafe {
+            let conn_ref: *const Connection = &*conn;
+            let stmt = (*conn_ref).prepare(query)?;
+            std::mem::transmute::<Statement<'_>, Statement<'static>>(stmt)
+        };
+
+        Ok(Self {
+            stmt,
+            conn,
+            interrupt,
+        })
+    }
+
+    /// Returns the interrupt handle of the connection, unless a cursor is open on it.
+    pub fn interrupt(&self) -> Option<Arc<InterruptHandle>> {
+        self.interrupt.get()
+    }
+
+    pub fn columns(&self) -> Columns {
+        Columns::of(&self.stmt)
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.stmt.parameter_count()
+    }
+
+    pub fn get_mut(&mut self) -> &mut Statement<'static> {
+        &mut self.stmt
+    }
+
+    /// Returns the connection alongside the statement, for reads such as
+    /// `last_insert_rowid` that must follow an execution.
+    pub fn parts_mut(&mut self) -> (&Connection, &mut Statement<'static>) {
+        (&self.conn, &mut self.stmt)
+    }
+}
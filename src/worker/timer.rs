@@ -0,0 +1,84 @@
+use std::{
+    thread::{JoinHandle, spawn},
+    time::Instant,
+};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use godot::{global::printerr, meta::ToGodot};
+
+use crate::{error::InternalError, tasks::TaskContext, worker::messages::OutputMessage};
+
+/// Watches tasks from the moment they're created and expires them once their
+/// deadline passes.
+///
+/// The timer runs on its own thread, since the worker is blocked inside SQLite
+/// while the statement that has to be interrupted is running. A task that is still
+/// queued, possibly behind a transaction or a restore that holds its connection, is
+/// failed by the timer itself.
+#[derive(Debug)]
+pub struct Timer {
+    sender: Option<Sender<TaskContext>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Timer {
+    pub fn new(output_sender: Sender<OutputMessage>) -> Self {
+        let (sender, receiver) = crossbeam::channel::unbounded::<TaskContext>();
+        let handle = Some(spawn(move || timer_loop(receiver, output_sender)));
+        Self {
+            sender: Some(sender),
+            handle,
+        }
+    }
+
+    /// Starts watching `task_ctx`, if it has a deadline.
+    pub fn watch(&self, task_ctx: &TaskContext) {
+        if task_ctx.deadline().is_none() {
+            return;
+        }
+        if let Some(sender) = self.sender.as_ref() {
+            let _ = sender.send(task_ctx.clone());
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            printerr(&["timer thread panicked".to_variant()]);
+        }
+    }
+}
+
+fn timer_loop(receiver: Receiver<TaskContext>, output_sender: Sender<OutputMessage>) {
+    let mut watched: Vec<TaskContext> = Vec::new();
+
+    loop {
+        let next = watched.iter().filter_map(TaskContext::deadline).min();
+        let received = match next {
+            Some(deadline) => receiver.recv_deadline(deadline),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(task_ctx) => watched.push(task_ctx),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let now = Instant::now();
+        watched.retain(|task_ctx| match task_ctx.deadline() {
+            Some(deadline) if task_ctx.is_pending() && deadline <= now => {
+                if task_ctx.time_out() {
+                    let err = InternalError::Timeout.into();
+                    let _ = output_sender.send(OutputMessage::Failed(task_ctx.clone(), err));
+                }
+                false
+            }
+            _ => task_ctx.is_pending(),
+        });
+    }
+}